@@ -0,0 +1,121 @@
+//! The `--batch-file` format: one PR to merge per line, in order.
+//!
+//! Each line is a target, as accepted on the command line, optionally followed by flags which
+//! apply to that PR alone, e.g. `123 --no-autosquash`. Words are split like a shell would, minus
+//! expansions. Blank lines and lines starting with `#` are skipped.
+
+use anyhow::{bail, Context, Result};
+
+#[derive(Debug)]
+pub struct Entry {
+    pub target: String,
+    pub flags: Vec<String>,
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = split_words(line)
+            .with_context(|| format!("line {}", idx + 1))?
+            .into_iter();
+        let Some(target) = words.next() else {
+            continue;
+        };
+        entries.push(Entry {
+            target,
+            flags: words.collect(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Split `line` on whitespace, honouring quotes and backslash escapes.
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("unterminated double quote"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => bail!("dangling backslash"),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(split_words("a  b\tc").unwrap(), ["a", "b", "c"]);
+        assert_eq!(
+            split_words(r#"--pr-body-append 'two words' "and \"more\"""#).unwrap(),
+            ["--pr-body-append", "two words", "and \"more\""]
+        );
+        assert_eq!(split_words(r#"'a\b' "a\b""#).unwrap(), [r"a\b", r"a\b"]);
+        assert_eq!(split_words(r"a\ b \'c").unwrap(), ["a b", "'c"]);
+        assert_eq!(split_words(r#"x'y'"z" '' "#).unwrap(), ["xyz", ""]);
+    }
+
+    #[test]
+    fn unterminated() {
+        let err = |line| split_words(line).unwrap_err().to_string();
+        assert_eq!(err("'abc"), "unterminated single quote");
+        assert_eq!(err(r#""abc"#), "unterminated double quote");
+        assert_eq!(err(r#""abc\"#), "unterminated double quote");
+        assert_eq!(err(r"abc\"), "dangling backslash");
+    }
+
+    #[test]
+    fn entries() {
+        let entries =
+            parse("# merge these\n\n123 --no-autosquash\n  # indented comment\nfeat/x\n").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].target, "123");
+        assert_eq!(entries[0].flags, ["--no-autosquash"]);
+        assert_eq!(entries[1].target, "feat/x");
+        assert!(entries[1].flags.is_empty());
+
+        let err = parse("1\n2 'x\n").unwrap_err();
+        assert_eq!(format!("{err:#}"), "line 2: unterminated single quote");
+    }
+}
@@ -0,0 +1,138 @@
+//! Just enough of GitHub's `CODEOWNERS` format to tell who owns a path.
+//!
+//! Each rule is a gitignore-style pattern followed by its owners; the last matching rule wins.
+//! Negated patterns and character classes are not supported by GitHub, so not here either.
+
+/// Where GitHub looks for the file, in the order it looks.
+pub const PATHS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+#[derive(Debug)]
+struct Rule {
+    pattern: String,
+    owners: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+impl CodeOwners {
+    pub fn parse(contents: &str) -> Self {
+        let rules = contents
+            .lines()
+            .filter_map(|line| {
+                let line = line.split_once('#').map_or(line, |(line, _)| line);
+                let mut words = line.split_whitespace();
+                let pattern = words.next()?.to_owned();
+                let owners = words.map(ToOwned::to_owned).collect();
+                Some(Rule { pattern, owners })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// The owners of `path`, relative to the root of the repository.
+    ///
+    /// Empty if nobody owns it, which includes a matching rule with no owners.
+    pub fn owners(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| matches(&rule.pattern, path))
+            .map_or(&[], |rule| &rule.owners)
+    }
+}
+
+/// Whether the gitignore-style `pattern` matches `path` or one of its parent directories.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    // a pattern is relative to the root if it contains a slash anywhere but the end
+    let pattern = match pattern.strip_prefix('/') {
+        Some(pattern) => pattern.to_owned(),
+        None if pattern.contains('/') => pattern.to_owned(),
+        None => format!("**/{pattern}"),
+    };
+    // unlike gitignore, `dir/*` only matches files directly in `dir`
+    let contents = format!("{pattern}/**");
+    (!pattern.ends_with("/*") && glob(contents.as_bytes(), path.as_bytes()))
+        || (!dir_only && glob(pattern.as_bytes(), path.as_bytes()))
+}
+
+/// Match `path` against a glob where `*` and `?` stay within a path segment, and `**` does not.
+fn glob(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // zero or more whole directories
+            glob(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(idx, &c)| c == b'/' && glob(rest, &path[idx + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|idx| glob(rest, &path[idx..])),
+        [b'*', rest @ ..] => {
+            let segment = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+            (0..=segment).any(|idx| glob(rest, &path[idx..]))
+        }
+        [b'?', rest @ ..] => matches!(path, [c, path @ ..] if *c != b'/' && glob(rest, path)),
+        [c, rest @ ..] => matches!(path, [p, path @ ..] if p == c && glob(rest, path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchored_directory() {
+        assert!(matches("/docs/", "docs/guide.md"));
+        assert!(matches("/docs/", "docs/api/index.md"));
+        assert!(!matches("/docs/", "src/docs/guide.md"));
+        // a directory pattern doesn't match a file of the same name
+        assert!(!matches("/docs/", "docs"));
+    }
+
+    #[test]
+    fn unanchored_pattern() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/deep/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("target", "a/target/debug/out"));
+    }
+
+    #[test]
+    fn star_stays_in_directory() {
+        assert!(matches("docs/*", "docs/guide.md"));
+        assert!(!matches("docs/*", "docs/api/index.md"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+    }
+
+    #[test]
+    fn double_star_in_the_middle() {
+        assert!(matches("src/**/mod.rs", "src/mod.rs"));
+        assert!(matches("src/**/mod.rs", "src/a/b/mod.rs"));
+        assert!(!matches("src/**/mod.rs", "lib/a/mod.rs"));
+        assert!(matches("/apps/**/test", "apps/web/test/unit.rs"));
+    }
+
+    #[test]
+    fn last_rule_wins() {
+        let owners = CodeOwners::parse(
+            "# comment\n* @everyone\n*.rs @rust # inline comment\n/src/ci/ @ci @ops\n",
+        );
+        assert_eq!(owners.owners("README.md"), ["@everyone"]);
+        assert_eq!(owners.owners("src/main.rs"), ["@rust"]);
+        assert_eq!(owners.owners("src/ci/check.rs"), ["@ci", "@ops"]);
+    }
+
+    #[test]
+    fn rule_without_owners_unsets_ownership() {
+        let owners = CodeOwners::parse("* @everyone\n/vendor/\n");
+        assert_eq!(owners.owners("src/main.rs"), ["@everyone"]);
+        assert!(owners.owners("vendor/lib.rs").is_empty());
+        assert!(CodeOwners::parse("").owners("main.rs").is_empty());
+    }
+}
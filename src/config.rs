@@ -0,0 +1,372 @@
+//! Repository-wide defaults, read from `.merge-pr.toml` at the root of the working tree, and
+//! named profiles of defaults shared between repositories.
+//!
+//! Only the flat subset of TOML which we actually need is understood: one `key = value` pair
+//! per line, where a value is a string, boolean, number, or an array of those. Tables are
+//! rejected. Values are collected into a JSON object so that `serde` can do the rest.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{Map, Number, Value};
+
+use crate::BaseFetchTags;
+
+pub const FILE_NAME: &str = ".merge-pr.toml";
+
+/// Overrides where profiles are looked for first.
+pub const PROFILES_DIR_VAR: &str = "MERGE_PR_PROFILES_DIR";
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Flags are optional so that `false` in the repository's config can turn off a profile's
+    /// `true`.
+    pub require_linear_history: Option<bool>,
+    pub ensure_base_is_default: Option<bool>,
+    /// Relative paths are relative to the root of the working tree.
+    pub gh_config_dir: Option<PathBuf>,
+    pub gh_hostname: Option<String>,
+    /// See `--changelog-template`.
+    pub changelog_template: Option<String>,
+    /// Added to any `--pr-labels-required`.
+    pub required_labels: Vec<String>,
+    /// Added to any `--pr-labels-forbidden`.
+    pub forbidden_labels: Vec<String>,
+    /// Added to any `--required-ci-check`.
+    pub required_ci_checks: Vec<String>,
+    /// See `--pr-title-pattern`.
+    pub pr_title_pattern: Option<String>,
+    pub fetch_tags: Option<bool>,
+    pub base_fetch_tags: Option<BaseFetchTags>,
+}
+
+impl Config {
+    /// Load the config from `dir`, or the default config if there is no config file there.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::load_file(&path)
+    }
+
+    /// Load the profile called `name` from the first of [`profile_dirs`] which has it.
+    pub fn load_profile(name: &str) -> Result<Self> {
+        let dirs = profile_dirs();
+        let path = dirs
+            .iter()
+            .map(|dir| dir.join(format!("{name}.toml")))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                let dirs = dirs
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>();
+                anyhow!("no profile named `{name}` in {}", dirs.join(" or "))
+            })?;
+        Self::load_file(&path)
+    }
+
+    fn load_file(path: &Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let value = parse(&contents).with_context(|| format!("parsing {}", path.display()))?;
+        serde_json::from_value(value).with_context(|| format!("interpreting {}", path.display()))
+    }
+}
+
+/// Where profiles are looked for, in order: `$MERGE_PR_PROFILES_DIR`, then
+/// `merge-pr/profiles` in the user's config directory.
+pub fn profile_dirs() -> Vec<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    std::env::var_os(PROFILES_DIR_VAR)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(config_home.map(|dir| dir.join("merge-pr").join("profiles")))
+        .collect()
+}
+
+/// The names of all profiles, sorted and without duplicates.
+pub fn profiles() -> Vec<String> {
+    let mut names = profile_dirs()
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            Some(path.file_stem()?.to_str()?.to_owned())
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Parse the flat TOML subset described in the module docs into a JSON object.
+fn parse(contents: &str) -> Result<Value> {
+    let mut map = Map::new();
+    let mut lines = contents.lines().enumerate();
+    while let Some((idx, line)) = lines.next() {
+        let line_no = idx + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            bail!("line {line_no}: tables are not supported");
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {line_no}: expected `key = value`"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("line {line_no}: invalid key `{key}`");
+        }
+
+        // arrays may be spread across several lines
+        let mut value = value.trim().to_owned();
+        if value.starts_with('[') {
+            while !array_is_closed(&value) {
+                let (_, next) = lines
+                    .next()
+                    .ok_or_else(|| anyhow!("line {line_no}: unterminated array"))?;
+                value.push(' ');
+                value.push_str(strip_comment(next).trim());
+            }
+        }
+
+        let value = parse_value(&value).with_context(|| format!("line {line_no}: `{key}`"))?;
+        if map.insert(key.to_owned(), value).is_some() {
+            bail!("line {line_no}: duplicate key `{key}`");
+        }
+    }
+    Ok(Value::Object(map))
+}
+
+/// Iterate over the characters of `s` which are not part of a string literal.
+fn unquoted(s: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote = None;
+    let mut escaped = false;
+    s.char_indices().filter(move |&(_, c)| {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => return true,
+        }
+        false
+    })
+}
+
+/// Remove a trailing `# comment`, taking care not to cut a string value in half.
+fn strip_comment(line: &str) -> &str {
+    unquoted(line)
+        .find(|&(_, c)| c == '#')
+        .map_or(line, |(idx, _)| &line[..idx])
+}
+
+fn array_is_closed(value: &str) -> bool {
+    let depth = unquoted(value).fold(0_i32, |depth, (_, c)| match c {
+        '[' => depth + 1,
+        ']' => depth - 1,
+        _ => depth,
+    });
+    depth <= 0
+}
+
+fn parse_value(value: &str) -> Result<Value> {
+    let value = value.trim();
+    if let Some(inner) = value.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| anyhow!("unterminated array"))?;
+        return split_array(inner)?
+            .into_iter()
+            .map(parse_value)
+            .collect::<Result<_>>()
+            .map(Value::Array);
+    }
+    if let Some(inner) = value.strip_prefix('"') {
+        let inner = inner
+            .strip_suffix('"')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        return unescape(inner).map(Value::String);
+    }
+    if let Some(inner) = value.strip_prefix('\'') {
+        let inner = inner
+            .strip_suffix('\'')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        return Ok(Value::String(inner.to_owned()));
+    }
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    let digits = value.replace('_', "");
+    if let Ok(int) = digits.parse::<i64>() {
+        return Ok(Value::Number(int.into()));
+    }
+    if let Some(float) = digits.parse::<f64>().ok().and_then(Number::from_f64) {
+        return Ok(Value::Number(float));
+    }
+    bail!("unsupported value `{value}`")
+}
+
+/// Split the inside of an array on top-level commas.
+fn split_array(inner: &str) -> Result<Vec<&str>> {
+    let mut items = Vec::new();
+    let mut depth = 0_i32;
+    let mut start = 0;
+    for (idx, c) in unquoted(inner) {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&inner[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&inner[start..]);
+    // a trailing comma is permitted
+    if items.last().is_some_and(|item| item.trim().is_empty()) {
+        items.pop();
+    }
+    if items.iter().any(|item| item.trim().is_empty()) {
+        bail!("empty array element");
+    }
+    Ok(items)
+}
+
+fn unescape(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some(other) => bail!("unsupported escape `\\{other}`"),
+            None => bail!("dangling backslash"),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn comments_outside_strings_are_stripped() {
+        assert_eq!(strip_comment("a = 1 # one"), "a = 1 ");
+        assert_eq!(strip_comment(r##"a = "#1" # one"##), r##"a = "#1" "##);
+        assert_eq!(strip_comment(r#"a = '#1'"#), r#"a = '#1'"#);
+        assert_eq!(strip_comment(r##"a = "\"#" # one"##), r##"a = "\"#" "##);
+        let value = parse("# comment\nname = \"a # b\" # trailing\n").unwrap();
+        assert_eq!(value, json!({ "name": "a # b" }));
+    }
+
+    #[test]
+    fn values() {
+        let value = parse(
+            "string = \"a\\tb\"\nliteral = 'a\\tb'\nyes = true\nno = false\nint = 1_000\nfloat = 1.5",
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "string": "a\tb",
+                "literal": "a\\tb",
+                "yes": true,
+                "no": false,
+                "int": 1000,
+                "float": 1.5,
+            })
+        );
+        assert!(parse(r#"a = "\q""#).is_err());
+        assert!(parse("a = b").is_err());
+    }
+
+    #[test]
+    fn arrays_may_span_lines() {
+        let value = parse("labels = [\n  \"a\", # first\n  \"b]\",\n]\nnext = 1").unwrap();
+        assert_eq!(value, json!({ "labels": ["a", "b]"], "next": 1 }));
+        assert!(parse("labels = [\n  \"a\",\n").is_err());
+    }
+
+    #[test]
+    fn trailing_commas() {
+        assert_eq!(split_array(r#""a", "b","#).unwrap(), [r#""a""#, r#" "b""#]);
+        assert_eq!(split_array(r#""a,b""#).unwrap(), [r#""a,b""#]);
+        assert_eq!(split_array("[1, 2], 3").unwrap(), ["[1, 2]", " 3"]);
+        assert!(split_array("1,,2").is_err());
+        assert_eq!(parse("a = []").unwrap(), json!({ "a": [] }));
+    }
+
+    #[test]
+    fn unescaping() {
+        assert_eq!(unescape(r#"a\"b\\c\nd"#).unwrap(), "a\"b\\c\nd");
+        assert!(unescape(r"a\").is_err());
+        assert!(unescape(r"\A").is_err());
+    }
+
+    #[test]
+    fn duplicate_keys_and_tables_are_rejected() {
+        let err = parse("a = 1\na = 2").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: duplicate key `a`");
+        let err = parse("[table]\na = 1").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: tables are not supported");
+        assert!(parse("a.b = 1").is_err());
+        assert!(parse("just words").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("merge-pr-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(FILE_NAME);
+
+        std::fs::write(&path, "require_linear_history = true\n").unwrap();
+        assert_eq!(
+            Config::load(&dir).unwrap().require_linear_history,
+            Some(true)
+        );
+        std::fs::write(&path, "require_linear_history = false\n").unwrap();
+        assert_eq!(
+            Config::load(&dir).unwrap().require_linear_history,
+            Some(false)
+        );
+
+        std::fs::write(&path, "require_linear_histroy = true\n").unwrap();
+        let err = Config::load(&dir).unwrap_err();
+        assert!(format!("{err:#}").contains("unknown field `require_linear_histroy`"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(Config::load(&dir).unwrap().require_linear_history, None);
+    }
+}
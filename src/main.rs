@@ -1,133 +1,1306 @@
-use std::{borrow::Cow, time::Duration};
+use std::{
+    borrow::Cow,
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        mpsc, Mutex, OnceLock, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+mod batch;
+mod codeowners;
+mod config;
+mod regex;
 
 use anyhow::{anyhow, bail, Context, Result};
-use clap::Parser;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use serde_json::Value;
 use spinners::{Spinner, Spinners};
-use xshell::{cmd, Shell};
+use xshell::{cmd, Cmd, Shell};
+
+use crate::{codeowners::CodeOwners, config::Config, regex::Regex};
 
 /// Merge this pull request, ensuring a linear history.
 ///
 /// Github's rebase-and-merge button doesn't fast-forward properly.
 /// This tool does it better.
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser, serde::Serialize)]
 struct Args {
-    /// Branch name or PR number to merge
+    /// Branch names or PR numbers to merge, in order
     ///
     /// Accepts 3 formats: a PR number, the name of a branch on the remote, or `<fork-owner>:<fork-branch-name>`.
-    branch_or_pr_number: Option<String>,
+    /// When omitted, merges the PR for the current branch.
+    #[serde(skip)]
+    branch_or_pr_number: Vec<String>,
+
+    /// Merge the open PR from this ref, e.g. `refs/heads/my-branch`.
+    ///
+    /// For push-triggered GitHub Actions workflows, with `--github-ref "$GITHUB_REF"`.
+    #[arg(long, value_name = "REF", conflicts_with = "branch_or_pr_number")]
+    github_ref: Option<String>,
+
+    /// Merge the PRs listed in this file, one per line, in order.
+    ///
+    /// Each line is a PR number or branch, as accepted on the command line, optionally followed
+    /// by flags for that PR alone, e.g. `123 --no-autosquash`. Flags which apply to the whole
+    /// run, like `--verbose`, have no effect there. Blank lines and `#` comments are skipped.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["branch_or_pr_number", "github_ref"]
+    )]
+    batch_file: Option<PathBuf>,
+
+    /// Print the settings in effect, and where each came from, then exit without merging.
+    ///
+    /// Settings come from the command line, the environment, `.merge-pr.toml`, a `--profile`,
+    /// or defaults. Lists which several of those add to, like `--required-ci-check`, name each
+    /// of them, e.g. `[CLI + file]`.
+    #[arg(long)]
+    #[serde(skip)]
+    print_config: bool,
+
+    /// Fill in settings from `<name>.toml` in the profiles directory, after `.merge-pr.toml`.
+    ///
+    /// Profiles have the same keys as `.merge-pr.toml`, and live in `$MERGE_PR_PROFILES_DIR`
+    /// or else `~/.config/merge-pr/profiles`.
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Print the names of the available profiles, then exit.
+    #[arg(long)]
+    #[serde(skip)]
+    list_profiles: bool,
+
+    /// When merging several PRs, push the base only once all of them have merged locally.
+    ///
+    /// Each PR is rebased onto the one before it, in the order given, and fast-forwarded into
+    /// the local base. The rebased branches are only pushed once all of them have merged. If any
+    /// of them fails, the local base and branches are reset and nothing is pushed to the base.
+    /// Every PR must have the same base.
+    #[arg(long)]
+    atomic: bool,
+
+    /// When merging several PRs, carry on with the rest after one fails.
+    ///
+    /// Each failure is reported as it happens, and again in a summary at the end, which exits
+    /// with an error if anything failed.
+    #[arg(long, visible_alias = "no-fail-fast", conflicts_with = "atomic")]
+    keep_going: bool,
+
+    /// Add the PR to GitHub's merge queue for its base instead of merging it here.
+    ///
+    /// The approval and CI checks still run first, but nothing is fetched, rebased or pushed
+    /// locally. The base branch must have a merge queue required by its branch protection.
+    #[arg(long, conflicts_with = "atomic")]
+    merge_queue: bool,
+
+    /// Rebase and push the branch, but leave the merge to GitHub's auto-merge.
+    ///
+    /// Instead of pushing the base, auto-merge is enabled on the PR, so that GitHub merges it
+    /// once its branch protection is satisfied. The local branch is kept.
+    #[arg(long, conflicts_with_all = ["atomic", "merge_queue", "no_rebase"])]
+    set_auto_merge: bool,
+
+    /// Merge through GitHub's API with `gh pr merge`, instead of locally.
+    ///
+    /// The approval and CI checks still run first, but nothing is fetched, rebased or pushed
+    /// locally.
+    #[arg(
+        long,
+        conflicts_with_all = ["atomic", "merge_queue", "set_auto_merge", "release"]
+    )]
+    github_merge_api: bool,
+
+    /// How GitHub merges the PR with `--github-merge-api`.
+    #[arg(long, value_enum, default_value_t = AutoMergeStrategy::Rebase)]
+    github_pr_merge_method: AutoMergeStrategy,
+
+    /// How GitHub should merge the PR with `--set-auto-merge`.
+    #[arg(long, value_enum, default_value_t = AutoMergeStrategy::Rebase)]
+    auto_merge_strategy: AutoMergeStrategy,
+
+    /// After merging, add the PR to `CHANGELOG.md` and tag the base as `v<VERSION>`.
+    ///
+    /// The changelog entry is committed on top of the pushed base, and that commit and the tag
+    /// are then pushed together.
+    #[arg(
+        long,
+        visible_alias = "tag-after-merge",
+        value_name = "VERSION",
+        conflicts_with_all = ["atomic", "merge_queue", "set_auto_merge"]
+    )]
+    release: Option<String>,
+
+    /// Template for the changelog entry added by `--release`.
+    ///
+    /// `{version}`, `{date}`, `{number}`, `{title}` and `{body}` are replaced with the details
+    /// of the release and the PR.
+    #[arg(long)]
+    changelog_template: Option<String>,
 
     /// When set, ignore CI and just merge straightaway
     #[arg(long)]
     ignore_ci: bool,
 
+    /// Treat CI as incomplete until a check run with this name or workflow name appears. May be
+    /// repeated.
+    ///
+    /// For checks which sometimes fail to start at all, and so would otherwise be silently
+    /// missing. Can also be set for the whole repository with `required_ci_checks = [...]` in
+    /// `.merge-pr.toml`.
+    #[arg(long, value_name = "NAME")]
+    required_ci_check: Vec<String>,
+
     /// When set, wait for CI to complete, then proceed
     #[arg(long)]
     wait_for_ci: bool,
 
-    /// Interval in seconds between CI polls. Only relevant with `--wait-for-ci`.
-    #[arg(long, default_value_t = 5.0)]
-    ci_poll_interval: f64,
+    /// When set, wait for the PR to be approved instead of refusing to merge it.
+    #[arg(long)]
+    wait_for_approval: bool,
+
+    /// How often to check whether the PR has been approved, with `--wait-for-approval`.
+    #[arg(long, default_value_t = HumanDuration(30.0))]
+    approval_poll_interval: HumanDuration,
+
+    /// Give up on a PR if it is still waiting for approval or CI after this long in all.
+    #[arg(long, value_name = "DURATION")]
+    max_wait: Option<HumanDuration>,
+
+    /// While waiting for CI, keep this file up to date with the merge's progress as JSON.
+    ///
+    /// The file is replaced atomically after every poll, and removed once the run succeeds.
+    /// Without a path, `merge-pr-<pid>.json` in the temporary directory is used.
+    #[arg(long, value_name = "PATH")]
+    #[serde(serialize_with = "serialize_progress_file")]
+    progress_file: Option<Option<PathBuf>>,
+
+    /// Append every check run's status to this file as JSON on every poll of the PR's status.
+    ///
+    /// One line per poll, with a timestamp, for seeing afterwards which checks were slow.
+    #[arg(long, value_name = "PATH")]
+    log_all_checks: Option<PathBuf>,
+
+    /// Print the commands which would change anything, prefixed with `[DRY-RUN]`, instead of
+    /// running them.
+    ///
+    /// Commands which only read, like `git rev-parse` or `gh pr view`, still run. Later steps
+    /// carry on as if the printed commands had succeeded, so this shows the plan for a merge
+    /// rather than exactly what would happen.
+    #[arg(long, conflicts_with_all = ["dry_run", "check_only"])]
+    dry_run_commands: bool,
+
+    /// Print a line per CI poll instead of showing a spinner.
+    ///
+    /// This is the default when stdout is not a terminal.
+    #[arg(long)]
+    no_spinner: bool,
+
+    /// When to color output, including the CI spinner.
+    ///
+    /// `auto` colors output to a terminal unless `NO_COLOR` is set.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Shorthand for `--color never`.
+    #[arg(long, conflicts_with = "color")]
+    no_color: bool,
 
-    /// How long to wait (seconds) between push attempts.
+    /// Interval between CI polls, e.g. `30s` or `2m`. Only relevant with `--wait-for-ci`.
+    #[arg(long, default_value_t = HumanDuration(5.0))]
+    ci_poll_interval: HumanDuration,
+
+    /// How long to wait before the first retry of the push to the base.
+    ///
+    /// This program will retry the final push to the base after this interval,
+    /// in order to ensure that github has the chance to synchronize itself.
+    /// The wait doubles with each subsequent retry.
+    #[arg(short = 'i', long, default_value_t = HumanDuration(2.5))]
+    push_retry_interval: HumanDuration,
+
+    /// Give up on a git fetch or push over HTTP which stalls for this long.
+    ///
+    /// Sets `GIT_HTTP_LOW_SPEED_TIME`, unless that is already set and this is not given. `gh`
+    /// has no equivalent setting, so its requests are not limited.
+    #[arg(long, default_value_t = HumanDuration(60.0))]
+    network_timeout: HumanDuration,
+
+    /// Exit if any single git command takes longer than this, e.g. one stuck on a prompt.
+    ///
+    /// The command itself can't be cancelled and is left running. This includes anything run
+    /// by `git rebase`, such as `--rebase-exec`.
+    #[arg(long, value_name = "DURATION")]
+    timeout_per_step: Option<HumanDuration>,
+
+    /// How many times to retry the final push to the base.
     ///
-    /// This program will retry the final push of to the base exactly once,
-    /// after this interval, in order to ensure that github has the chance
-    /// to synchronize itself.
-    #[arg(short = 'i', long, default_value_t = 2.5)]
-    push_retry_interval: f64,
+    /// Before each retry the base is fetched again; if it has moved on, the branch is
+    /// rebased onto it. Authentication failures are not retried.
+    #[arg(long, default_value_t = 3)]
+    max_push_retries: u32,
+
+    /// Pass this to the server with `--push-option` when pushing the PR's branch and the base,
+    /// e.g. `ci.skip`. May be repeated.
+    #[arg(long, value_name = "OPTION")]
+    push_options: Vec<String>,
 
-    /// How long to wait (seconds) after pushing the rebased branch before pushing the
-    /// base branch.
+    /// The ref to push the base to, with `{base}` standing for its name, e.g.
+    /// `refs/for/{base}` for a review server in front of the remote.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{base}",
+        value_parser = |template: &str| ref_template(template, "base"),
+    )]
+    ref_format: String,
+
+    /// The ref to force-push the PR's branch to, with `{branch}` standing for its name.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{branch}",
+        value_parser = |template: &str| ref_template(template, "branch"),
+    )]
+    branch_refspec: String,
+
+    /// How long to wait after pushing the rebased branch before pushing the base branch.
     ///
     /// This will give github some time to handle the push to the branch before it gets
     /// merged and (potentially) deleted.
-    #[arg(short = 'w', long, default_value_t = 4.0)]
-    wait_after_rebase: f64,
+    #[arg(short = 'w', long, default_value_t = HumanDuration(4.0))]
+    wait_after_rebase: HumanDuration,
+
+    /// How long to wait after pushing the base branch before deleting the merged branch.
+    ///
+    /// This gives github time to mark the PR as merged before it sees the branch go, which
+    /// keeps the PR's timeline in order. Nothing is waited for with `--retain-branch`.
+    #[arg(long, default_value_t = HumanDuration(1.0))]
+    wait_after_push: HumanDuration,
 
     /// When set, perform checks but do not actually change the repo state.
+    ///
+    /// The commits which would be added to the base are shown as patches, as with
+    /// `--dry-run-diff`.
     #[arg(short, long)]
     dry_run: bool,
 
+    /// Show the commits which would be added to the base as patches, paged through `less` on a
+    /// terminal. Implies `--dry-run`.
+    #[arg(long, conflicts_with_all = ["check_only", "dry_run_commands"])]
+    dry_run_diff: bool,
+
+    /// Like `--dry-run-diff`, but save the patches as numbered `.patch` files in this
+    /// directory, ready for `git am`.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["check_only", "dry_run_commands", "dry_run_diff"]
+    )]
+    format_patch_output: Option<PathBuf>,
+
+    /// When set, run every check without changing the repo state, then report all failures.
+    ///
+    /// Unlike `--dry-run`, this doesn't stop at the first failure, and prints nothing on
+    /// success: the exit code is the result.
+    #[arg(long, visible_alias = "validate", conflicts_with = "dry_run")]
+    check_only: bool,
+
+    /// Do not rebase; merge the branch exactly as it is on the remote.
+    ///
+    /// The branch must already be on top of its base, or the fast-forward merge will fail.
+    #[arg(long, conflicts_with = "no_autosquash")]
+    no_rebase: bool,
+
+    /// Fix whitespace errors, such as trailing whitespace, in every rebased commit.
+    ///
+    /// This passes `--whitespace=fix` to `git rebase`. It changes the content of the commits,
+    /// so it invalidates any existing commit signatures, and always requires a force-push.
+    #[arg(
+        long,
+        overrides_with = "no_rebase_whitespace",
+        conflicts_with = "no_rebase"
+    )]
+    rebase_whitespace: bool,
+
+    /// Leave whitespace in rebased commits alone. This is the default.
+    #[arg(long, overrides_with = "rebase_whitespace")]
+    no_rebase_whitespace: bool,
+
+    /// What to do when the rebase fails.
+    #[arg(long, value_enum, default_value_t = OnFailure::Abort)]
+    on_failure: OnFailure,
+
+    /// How to resolve rebase conflicts in files matching `--generated-file-pattern`.
+    ///
+    /// Conflicts are only resolved if every conflicting file matches; otherwise the rebase
+    /// fails as usual.
+    #[arg(long, value_enum, default_value_t = ConflictStrategy::Manual)]
+    rebase_conflict_strategy: ConflictStrategy,
+
+    /// A gitignore-style pattern for generated files, e.g. `*.lock`, whose rebase conflicts
+    /// `--rebase-conflict-strategy` resolves. May be repeated.
+    #[arg(
+        long,
+        value_name = "GLOB",
+        required_if_eq_any = [
+            ("rebase_conflict_strategy", "ours"),
+            ("rebase_conflict_strategy", "theirs"),
+        ],
+    )]
+    generated_file_pattern: Vec<String>,
+
+    /// Sign the commits written by the rebase, and any others this creates, with GPG.
+    #[arg(long)]
+    gpg_sign: bool,
+
+    /// The key to sign with under `--gpg-sign`, instead of `user.signingKey`.
+    ///
+    /// A short (8 hex digit) or long (16 hex digit) key ID, or a full fingerprint.
+    #[arg(long, value_name = "ID", value_parser = parse_gpg_key_id)]
+    gpg_key_id: Option<String>,
+
+    /// Override a git config setting for the git commands run by this tool. May be repeated.
+    ///
+    /// Uses git's `GIT_CONFIG_COUNT` environment variables, so nobody's config files are touched.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_git_config)]
+    gitconfig: Vec<(String, String)>,
+
+    /// Shell command to run on the rebased branch before it is pushed. May be repeated.
+    ///
+    /// Commands run in the order given. Changes they make to tracked files are amended into
+    /// the last commit. If one fails, the rebase is undone and nothing is merged.
+    #[arg(long, value_name = "CMD", conflicts_with = "no_rebase")]
+    exec_after_rebase: Vec<String>,
+
+    /// Run this shell command just before pushing the base, with the merged commits checked
+    /// out, and refuse to push if it fails.
+    ///
+    /// Unlike `--exec-after-rebase`, this sees the base exactly as it is about to be pushed. If
+    /// the PR has to be rebased again before a retried push, the command is run again too.
+    #[arg(long, value_name = "CMD")]
+    pre_push_check: Option<String>,
+
+    /// Shell command to run after each commit is rebased, as `git rebase --exec`. May be repeated.
+    ///
+    /// If it fails, the rebase stops at that commit, and `--on-failure` applies as for a conflict.
+    #[arg(long, value_name = "CMD", conflicts_with = "no_rebase")]
+    rebase_exec: Vec<String>,
+
+    /// Credit the PR's approving reviewers with `Co-authored-by:` trailers on its last commit.
+    ///
+    /// Reviewers' public emails are used where available, and otherwise their github no-reply
+    /// addresses. This amends the last commit, so it will need signing again.
+    #[arg(long, conflicts_with = "no_rebase")]
+    co_author_from_pr_reviewers: bool,
+
+    /// The most co-authors to credit with `--co-author-from-pr-reviewers`.
+    #[arg(long, default_value_t = 5)]
+    max_co_authors: usize,
+
     /// When set, retain the merged branch instead of deleting it locally.
+    ///
+    /// This only concerns the local branch; the PR's branch on the remote is left alone unless
+    /// `--delete-remote-branch` is given.
     #[arg(short, long)]
     retain_branch: bool,
 
+    /// Also delete the PR's branch from the remote it was pushed to, once merged.
+    ///
+    /// Off by default, as github can be set to do this itself. Combined with `--retain-branch`
+    /// this needs `--yes`, since deleting the remote branch but keeping the local one is
+    /// unusual.
+    #[arg(
+        long,
+        conflicts_with_all = ["merge_queue", "set_auto_merge", "github_merge_api"]
+    )]
+    delete_remote_branch: bool,
+
+    /// Leave the PR's branch on the remote alone. This is the default.
+    #[arg(long, overrides_with = "delete_remote_branch")]
+    no_delete_remote_branch: bool,
+
+    /// Confirm unusual combinations of options rather than refusing them.
+    #[arg(long, visible_alias = "confirm")]
+    yes: bool,
+
+    /// How long to wait after pushing the base before deleting the merged branch locally.
+    ///
+    /// Gives editors which still have the branch open time to let go of it. Nothing is waited
+    /// for with `--retain-branch`.
+    #[arg(long, default_value_t = HumanDuration(0.0))]
+    branch_delete_delay: HumanDuration,
+
+    /// After merging, also delete local branches matching this glob, e.g. `feat/*`, which are
+    /// fully merged into the base.
+    ///
+    /// Unmerged branches are listed and kept. Under `--dry-run`, the branches which would be
+    /// deleted are listed instead.
+    #[arg(long, value_name = "GLOB")]
+    branch_cleanup_pattern: Option<String>,
+
+    /// Run in this directory instead of the current one.
+    ///
+    /// Must be the root of a git working tree.
+    #[arg(short = 'C', long)]
+    workdir: Option<PathBuf>,
+
+    /// Look for `pre-merge` and `post-merge` hooks in this directory.
+    ///
+    /// Executable hooks found there are run with the branch, base and PR number as arguments:
+    /// `pre-merge` just before merging into the base, where failing aborts the merge, and
+    /// `post-merge` once the base has been pushed. Defaults to `hooks/merge-pr` in the git dir.
+    #[arg(long, value_name = "DIR")]
+    hooks_dir: Option<PathBuf>,
+
+    /// Limit fetches to this many commits of history; 0 fetches everything.
+    ///
+    /// If the rebase fails because the shallow history doesn't reach the merge base,
+    /// the full history is fetched and the rebase retried.
+    #[arg(long, default_value_t = 0)]
+    fetch_depth: u32,
+
+    /// Also fetch tags when fetching the PR's branch, which by default fetches no tags.
+    ///
+    /// Can also be enabled for the whole repository with `fetch_tags = true` in `.merge-pr.toml`.
+    #[arg(long)]
+    fetch_tags: bool,
+
+    /// Which tags to fetch along with the base branch [default: follow].
+    ///
+    /// Can also be set for the whole repository with `base_fetch_tags = "..."` in
+    /// `.merge-pr.toml`.
+    #[arg(long, value_enum)]
+    base_fetch_tags: Option<BaseFetchTags>,
+
     /// Name of the relevant git remote.
+    ///
+    /// This sets both the fetch and the push remote; see `--fetch-remote` and `--push-remote`.
     #[arg(short = 'R', long, default_value = "origin")]
     remote: String,
 
+    /// Name of the git remote to fetch from, if it differs from `--remote`.
+    #[arg(long)]
+    fetch_remote: Option<String>,
+
+    /// Name of the git remote to push to, if it differs from `--remote`.
+    #[arg(long)]
+    push_remote: Option<String>,
+
+    /// Name of the git remote holding the base branch, if it differs from `--remote`.
+    ///
+    /// For triangular workflows: the base is fetched from, rebased onto, and pushed to this
+    /// remote, while the PR branch stays on `--remote`. PRs are looked up in this remote's
+    /// repository on github.
+    #[arg(long, visible_alias = "upstream-remote")]
+    base_remote: Option<String>,
+
+    /// SSH private key to fetch from and push to a PR's fork with.
+    ///
+    /// Only used for the temporary remote added for a fork, not for `--remote` and friends.
+    #[arg(long, value_name = "PATH")]
+    fork_ssh_key: Option<PathBuf>,
+
     /// Do not automatically autosquash.
     ///
     /// By default, this tool will automatically autosquash fixup commits.
     /// If for some reason that behavior is undesirable, this flag will disable it.
     #[arg(long)]
     no_autosquash: bool,
+
+    /// Refuse to merge `fixup!`, `squash!` or `amend!` commits as they are, unless confirmed
+    /// with `--yes`.
+    ///
+    /// This is the default with `--no-autosquash`; the flag extends it to `--no-rebase` and
+    /// `--require-up-to-date`, which don't autosquash either.
+    #[arg(long)]
+    no_autosquash_detect: bool,
+
+    /// Print each git and gh command to stderr before running it.
+    ///
+    /// Pass twice (`-vv`) to also print the captured output of each command.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Refuse to merge a branch which contains merge commits.
+    ///
+    /// Can also be enabled for the whole repository with `require_linear_history = true`
+    /// in `.merge-pr.toml`.
+    #[arg(long)]
+    require_linear_history: bool,
+
+    /// Refuse to merge if the base has new commits since the PR was last updated.
+    ///
+    /// Instead of rebasing over them silently, this asks the author to update the branch, so
+    /// that they get to see the final state of the base before it merges.
+    #[arg(long)]
+    require_rebased_on_latest: bool,
+
+    /// Refuse to merge unless the branch already contains every commit on the base, and skip
+    /// rebasing it.
+    ///
+    /// Stricter than `--require-rebased-on-latest`, which allows base commits from before the
+    /// PR was last updated.
+    #[arg(long)]
+    require_up_to_date: bool,
+
+    /// Refuse to merge a PR whose base is not the repository's default branch.
+    ///
+    /// Can also be enabled for the whole repository with `ensure_base_is_default = true` in
+    /// `.merge-pr.toml`.
+    #[arg(long)]
+    ensure_base_is_default: bool,
+
+    /// Merge PRs into other bases than the default branch, e.g. release branches. This is the
+    /// default.
+    #[arg(long, overrides_with = "ensure_base_is_default")]
+    allow_non_default_base: bool,
+
+    /// Refuse to merge a PR whose base is its own branch, on the remote it would be pushed to.
+    /// This is the default.
+    ///
+    /// Merging such a PR would force-push to and from the same ref.
+    #[arg(long)]
+    verify_pr_not_updating_base: bool,
+
+    /// Skip the check of `--verify-pr-not-updating-base`.
+    #[arg(long, overrides_with = "verify_pr_not_updating_base")]
+    no_verify_pr_not_updating_base: bool,
+
+    /// Refuse to merge unless an owner of every changed file has approved.
+    ///
+    /// Owners come from the base branch's `CODEOWNERS`, for repositories where GitHub does not
+    /// enforce code owner reviews itself.
+    #[arg(long)]
+    codeowners_check: bool,
+
+    /// List the CI checks which branch protection requires on the base, with their current
+    /// state for the PR, then exit without merging.
+    #[arg(long)]
+    show_required_checks: bool,
+
+    /// Print the tree merging the PR would produce, any conflicts, and the commits it would
+    /// add, then exit without merging.
+    ///
+    /// Nothing but remote-tracking refs is changed, and the PR need not be approved. Needs git
+    /// 2.38 or later. With `--json`, the preview is printed as JSON.
+    #[arg(long, conflicts_with = "show_required_checks")]
+    preview_tree: bool,
+
+    /// Warn if branch protection on the base requires no CI checks at all, since then
+    /// nothing stops a PR being merged without any CI having run. The merge goes ahead.
+    #[arg(long)]
+    warn_no_required_checks: bool,
+
+    /// Refuse to merge while any requested reviewer has yet to review, even if approved.
+    #[arg(long)]
+    block_on_pending_reviews: bool,
+
+    /// Refuse to merge unless the merge base of the PR and its base is this commit.
+    ///
+    /// Checked once both have been fetched, before anything is changed, so that a base which
+    /// has moved on since the PR was last validated is caught. Abbreviated shas are accepted.
+    #[arg(long, value_name = "SHA", value_parser = parse_sha)]
+    merge_base_sha: Option<String>,
+
+    /// Only push the base if it is still at this commit on the remote.
+    ///
+    /// Optimistic concurrency control: the push is made with `--force-with-lease` expecting
+    /// this sha, so that it is rejected if anyone else has pushed to the base since, instead of
+    /// rebasing on top of their work. A rejected push is not retried.
+    #[arg(long, value_name = "SHA", value_parser = parse_sha)]
+    verify_remote_head: Option<String>,
+
+    /// Fetch the PR's branch again just before rebasing it, and refuse to merge if it has
+    /// changed since it was first fetched, e.g. because its author force-pushed in between.
+    #[arg(long)]
+    abort_on_unverified_force_push: bool,
+
+    /// Refuse to merge unless the base has exactly this many commits on the remote before
+    /// anything is pushed to it, to catch a release being merged twice.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["merge_queue", "set_auto_merge", "github_merge_api"]
+    )]
+    base_commit_count_before: Option<u64>,
+
+    /// Fail unless the base has exactly this many commits once it has been pushed.
+    ///
+    /// The push has happened by then, so this only stops what would follow it, like deleting
+    /// the branch, and flags the run as failed.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["merge_queue", "set_auto_merge", "github_merge_api"]
+    )]
+    base_commit_count_after: Option<u64>,
+
+    /// Refuse to merge unless the PR has this label. May be repeated.
+    ///
+    /// Labels are compared case-insensitively. Can also be set for the whole repository with
+    /// `required_labels = [...]` in `.merge-pr.toml`.
+    #[arg(long, value_name = "LABEL")]
+    pr_labels_required: Vec<String>,
+
+    /// Refuse to merge if the PR has this label, e.g. `do-not-merge`. May be repeated.
+    ///
+    /// Labels are compared case-insensitively. Can also be set for the whole repository with
+    /// `forbidden_labels = [...]` in `.merge-pr.toml`.
+    #[arg(long, value_name = "LABEL")]
+    pr_labels_forbidden: Vec<String>,
+
+    /// Refuse to merge unless the PR title matches this regular expression, e.g.
+    /// `^(feat|fix|chore)(\(.+\))?: `.
+    ///
+    /// The title is checked before reviews or CI, being the quickest thing to fix. Can also be
+    /// set for the whole repository with `pr_title_pattern = "..."` in `.merge-pr.toml`.
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pr_title_pattern: Option<String>,
+
+    /// Append this text to the PR description, separated by a blank line, before merging.
+    #[arg(long)]
+    pr_body_append: Option<String>,
+
+    /// Like `--pr-body-append`, but read the text from this file.
+    #[arg(long, conflicts_with = "pr_body_append")]
+    pr_body_file: Option<PathBuf>,
+
+    /// Prepend this text to the PR description, separated by a blank line, before merging.
+    #[arg(long)]
+    pr_body_prepend: Option<String>,
+
+    /// Assign the PR to this user before merging; may be given more than once.
+    ///
+    /// `@me` is whoever `gh` is logged in as.
+    #[arg(long, value_name = "LOGIN")]
+    pr_assignee: Vec<String>,
+
+    /// Configuration directory for `gh`, for selecting between several accounts.
+    ///
+    /// Sets `GH_CONFIG_DIR` for every `gh` invocation.
+    #[arg(long, env = "MERGE_PR_GH_CONFIG_DIR")]
+    gh_config_dir: Option<PathBuf>,
+
+    /// Github hostname for `gh` to use, e.g. for Github Enterprise.
+    ///
+    /// Sets `GH_HOST` for every `gh` invocation.
+    #[arg(long, env = "MERGE_PR_GH_HOST")]
+    gh_hostname: Option<String>,
+
+    /// Ask for this version of GitHub's REST API, as `YYYY-MM-DD`, instead of gh's default.
+    #[arg(long, value_name = "VERSION", value_parser = parse_api_version)]
+    github_api_version: Option<String>,
+
+    /// Post a notification to this Slack incoming webhook after a successful merge.
+    ///
+    /// Failing to notify Slack produces a warning, but does not fail the merge.
+    /// Requires `curl`.
+    #[arg(long, env = "MERGE_PR_SLACK_WEBHOOK", hide_env_values = true)]
+    slack_webhook: Option<String>,
+
+    /// After merging, close the issues the PR description references with `Fixes #123` and
+    /// the like.
+    ///
+    /// Only issues in the same repository are closed. Failing to close one produces a warning.
+    /// Under `--dry-run`, they are listed instead.
+    #[arg(
+        long,
+        conflicts_with_all = ["merge_queue", "set_auto_merge", "github_merge_api"]
+    )]
+    close_issues: bool,
+
+    /// Leave issues referenced by the PR alone. This is the default.
+    ///
+    /// This can't stop github closing issues referenced by the merged commits' messages.
+    #[arg(long, overrides_with = "close_issues")]
+    no_close_issues: bool,
+
+    /// Print how long each phase of the merge took once it completes.
+    #[arg(long)]
+    timings: bool,
+
+    /// Once merged, comment on the PR with how: the commits it was rebased from and to, whether
+    /// it was force-pushed, the CI checks which passed, how long it took and who merged it.
+    #[arg(
+        long,
+        conflicts_with_all = ["merge_queue", "set_auto_merge", "github_merge_api"]
+    )]
+    report_to_pr: bool,
+
+    /// If a merge fails after the PR has passed its checks, comment on the PR with why.
+    ///
+    /// `{branch}` and `{base}` in the message are replaced, and the error follows it. Without a
+    /// message, a standard one is used.
+    #[arg(long, value_name = "MESSAGE", visible_alias = "notify-on-failure")]
+    #[serde(serialize_with = "serialize_failure_comment")]
+    pr_comment_on_failure: Option<Option<String>>,
+
+    /// Markdown template for the `--report-to-pr` comment.
+    ///
+    /// `{branch}`, `{base}`, `{from}`, `{to}`, `{force_pushed}`, `{commits}`, `{checks}`,
+    /// `{duration}` and `{merged_by}` are replaced with the details of the merge.
+    #[arg(long, value_name = "PATH", requires = "report_to_pr")]
+    report_template_file: Option<PathBuf>,
+
+    /// Once the PR passes its checks, post a commit status with this context to its head.
+    ///
+    /// This uses the legacy statuses API rather than checks, for dashboards which follow it.
+    #[arg(long, value_name = "CONTEXT")]
+    set_pr_status: Option<String>,
+
+    /// The state of the `--set-pr-status` status.
+    #[arg(long, value_enum, default_value_t = StatusState::Success, requires = "set_pr_status")]
+    status_state: StatusState,
+
+    /// The description of the `--set-pr-status` status.
+    #[arg(long, requires = "set_pr_status")]
+    status_description: Option<String>,
+
+    /// Read the PR's head branch and repository from this file instead of asking github.
+    ///
+    /// The file holds `gh pr view` JSON, as written by `--save-pr-data`. Only applies when a
+    /// PR number or `<fork-owner>:<branch>` is given.
+    #[arg(long, value_name = "PATH", conflicts_with = "save_pr_data")]
+    pr_data_file: Option<PathBuf>,
+
+    /// Once every PR has merged, write the shas before and after to this file as JSON.
+    ///
+    /// One object per merged PR, one per line. Nothing is written if anything fails.
+    #[arg(long, value_name = "PATH")]
+    save_state_on_success: Option<PathBuf>,
+
+    /// Once every PR has merged, append step outputs to this file in the `key=value` format of
+    /// GitHub Actions, e.g. `--github-output "$GITHUB_OUTPUT"`.
+    ///
+    /// Written are `merged_branch`, `merged_base`, `new_base_sha` and `pr_number` of the last
+    /// PR merged.
+    #[arg(long, value_name = "PATH")]
+    github_output: Option<PathBuf>,
+
+    /// Save the JSON describing the PR's head, as fetched from github, to this file.
+    #[arg(long, value_name = "PATH")]
+    save_pr_data: Option<PathBuf>,
+
+    /// Print the result of a successful merge as JSON on stdout.
+    #[arg(long)]
+    json: bool,
+
+    /// Print a table summarizing a successful merge.
+    ///
+    /// With `--json`, the same rows are included under a `"summary"` key instead.
+    #[arg(long)]
+    summary: bool,
 }
 
-fn ensure_tool(sh: &Shell, tool_name: &str) -> Result<()> {
-    if cfg!(windows) {
-        cmd!(sh, "where {tool_name}")
+/// How much detail to print about the commands we run; set once from `--verbose`.
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+
+fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Whether to color output; set once from `--color` and `NO_COLOR`.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+fn use_color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// `text` wrapped in the SGR escape `code`, when coloring output.
+fn paint(code: u8, text: impl Display) -> String {
+    if use_color() {
+        format!("\x1b[{code}m{text}\x1b[0m")
     } else {
-        cmd!(sh, "which {tool_name}")
+        text.to_string()
     }
-    .quiet()
-    .ignore_stdout()
-    .run()
-    .map_err(|_| anyhow!("tool `{tool_name}` is required"))
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum CiState {
-    Success,    // all runs successful
-    Incomplete, // at least 1 run not yet complete, but no failures
-    Fail,       // at least 1 run failed
-}
+/// Set from `--github-api-version`.
+static GITHUB_API_VERSION: OnceLock<String> = OnceLock::new();
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct CheckRun {
-    name: String,
-    workflow_name: String,
-    status: Option<String>,
-    conclusion: String,
+/// Arguments for `gh api` selecting the REST API version from `--github-api-version`.
+fn api_version_args() -> Vec<String> {
+    GITHUB_API_VERSION
+        .get()
+        .map(|version| vec!["-H".to_owned(), format!("X-GitHub-Api-Version: {version}")])
+        .unwrap_or_default()
 }
 
-impl CheckRun {
-    fn is_successy(&self) -> bool {
-        self.status.as_deref() == Some("COMPLETED")
-            && (self.conclusion == "SUCCESS" || self.conclusion == "SKIPPED")
+fn parse_api_version(version: &str) -> Result<String, String> {
+    let is_date = version.len() == 10
+        && version.char_indices().all(|(idx, c)| match idx {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    if is_date {
+        Ok(version.to_owned())
+    } else {
+        Err("expected a date like 2022-11-28".into())
     }
+}
 
-    fn state(&self) -> CiState {
-        match (
-            self.status.as_deref().unwrap_or_default(),
-            self.conclusion.as_str(),
-        ) {
-            ("COMPLETED", "SUCCESS" | "SKIPPED" | "NEUTRAL") => CiState::Success,
-            ("QUEUED" | "IN_PROGRESS" | "WAITING" | "REQUESTED" | "PENDING", "") => {
-                CiState::Incomplete
-            }
-            ("COMPLETED", "FAILURE" | "CANCELLED" | "TIMED_OUT" | "ACTION_REQUIRED") => {
-                CiState::Fail
-            }
-            (status, conclusion) => {
-                eprintln!(
-                    "unxpected (status, conclusion) for {} / {}: ({status}, {conclusion})",
-                    self.workflow_name, self.name
-                );
-                CiState::Fail
-            }
-        }
-    }
+/// Set from `--timeout-per-step`.
+static STEP_TIMEOUT: OnceLock<HumanDuration> = OnceLock::new();
+
+/// What exiting from the watchdog has to clean up itself, since no destructors run then.
+struct LeftBehind {
+    /// Temporary fork remotes, with the directory to remove them from.
+    remotes: Vec<(PathBuf, String)>,
+    files: Vec<PathBuf>,
 }
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(tag = "__typename")]
+static LEFT_BEHIND: Mutex<LeftBehind> = Mutex::new(LeftBehind {
+    remotes: Vec::new(),
+    files: Vec::new(),
+});
+
+fn left_behind() -> std::sync::MutexGuard<'static, LeftBehind> {
+    LEFT_BEHIND.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Remove whatever is in [`LEFT_BEHIND`], saying what couldn't be.
+fn clean_up_left_behind() {
+    let left = left_behind();
+    for (dir, name) in &left.remotes {
+        let removed = std::process::Command::new("git")
+            .args(["remote", "remove", name])
+            .current_dir(dir)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !removed {
+            eprintln!(
+                "warning: left the temporary remote {name} behind in {}; remove it with \
+                 `git remote remove {name}`",
+                dir.display()
+            );
+        }
+    }
+    for path in &left.files {
+        if let Err(err) = std::fs::remove_file(path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("warning: left {} behind: {err}", path.display());
+            }
+        }
+    }
+}
+
+/// Exit the process if `cmd` is a git command which is still running after `--timeout-per-step`,
+/// after cleaning up what [`LEFT_BEHIND`] lists.
+///
+/// The watchdog is disarmed by dropping the returned sender.
+fn watchdog(cmd: &Cmd<'_>) -> Option<mpsc::Sender<()>> {
+    let timeout = *STEP_TIMEOUT.get()?;
+    let cmd = cmd.to_string();
+    if !cmd.starts_with("git ") {
+        return None;
+    }
+    let (disarm, armed) = mpsc::channel();
+    std::thread::spawn(move || {
+        let elapsed = armed.recv_timeout(Duration::from_secs_f64(timeout.secs()));
+        if elapsed == Err(mpsc::RecvTimeoutError::Timeout) {
+            eprintln!("error: `{cmd}` did not finish within {timeout}; giving up");
+            clean_up_left_behind();
+            std::process::exit(1);
+        }
+    });
+    Some(disarm)
+}
+
+/// Set from `--dry-run-commands`.
+static DRY_RUN_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+/// Whether `cmd` should be printed instead of run, under `--dry-run-commands`.
+fn skip_in_dry_run(cmd: &Cmd<'_>) -> bool {
+    if !DRY_RUN_COMMANDS.load(Ordering::Relaxed) {
+        return false;
+    }
+    let read_only = is_read_only(cmd);
+    if !read_only {
+        eprintln!("[DRY-RUN] {cmd}");
+    }
+    !read_only
+}
+
+/// Whether `cmd` leaves the repository, the filesystem and github as they were.
+///
+/// This errs on the side of caution: anything not known to only read is assumed to write.
+fn is_read_only(cmd: &Cmd<'_>) -> bool {
+    const GH_API_WRITES: [&str; 7] = [
+        "-X",
+        "--method",
+        "-f",
+        "-F",
+        "--field",
+        "--raw-field",
+        "--input",
+    ];
+    let cmd = cmd.to_string();
+    let mut words = cmd.split_whitespace();
+    match words.next() {
+        Some("git") => {
+            let mut subcommand = words.next();
+            while subcommand == Some("-c") {
+                words.next();
+                subcommand = words.next();
+            }
+            match subcommand {
+                Some(
+                    "--version" | "diff" | "format-patch" | "interpret-trailers" | "log"
+                    | "ls-remote" | "merge-base" | "merge-tree" | "rev-list" | "rev-parse" | "show"
+                    | "status",
+                ) => true,
+                Some("branch") => matches!(words.next(), Some("--show-current" | "--list")),
+                Some("remote") => words.next() == Some("get-url"),
+                _ => false,
+            }
+        }
+        Some("gh") => match (words.next(), words.next()) {
+            (Some("--version"), _)
+            | (Some("pr" | "repo"), Some("view" | "list"))
+            | (Some("auth"), Some("status")) => true,
+            (Some("api"), Some("graphql")) => !cmd.contains("mutation"),
+            (Some("api"), _) => !cmd.split_whitespace().any(|word| {
+                GH_API_WRITES
+                    .iter()
+                    .any(|flag| word == *flag || word.starts_with(&format!("{flag}=")))
+            }),
+            _ => false,
+        },
+        Some("which" | "where") => true,
+        _ => false,
+    }
+}
+
+/// Run a command, echoing it as `+ <cmd>` to stderr first when `--verbose` is set.
+fn verbose_run(cmd: Cmd<'_>) -> xshell::Result<()> {
+    if skip_in_dry_run(&cmd) {
+        return Ok(());
+    }
+    let _watchdog = watchdog(&cmd);
+    if verbosity() > 0 {
+        eprintln!("+ {cmd}");
+        // we've already echoed it; don't let xshell do it a second time
+        cmd.quiet().run()
+    } else {
+        cmd.run()
+    }
+}
+
+/// Read a command's stdout, echoing it as `+ <cmd>` to stderr first when `--verbose` is set.
+///
+/// At `-vv` and above the captured output is echoed as well.
+fn verbose_read(cmd: Cmd<'_>) -> xshell::Result<String> {
+    if skip_in_dry_run(&cmd) {
+        return Ok(String::new());
+    }
+    let _watchdog = watchdog(&cmd);
+    if verbosity() > 0 {
+        eprintln!("+ {cmd}");
+    }
+    let output = cmd.read()?;
+    if verbosity() > 1 {
+        eprintln!("{output}");
+    }
+    Ok(output)
+}
+
+fn ensure_tool(sh: &Shell, tool_name: &str) -> Result<()> {
+    verbose_run(
+        if cfg!(windows) {
+            cmd!(sh, "where {tool_name}")
+        } else {
+            cmd!(sh, "which {tool_name}")
+        }
+        .quiet()
+        .ignore_stdout(),
+    )
+    .map_err(|_| anyhow!("tool `{tool_name}` is required"))
+}
+
+/// Run a command, capturing both its stdout and stderr, echoing it first when `--verbose` is set.
+fn verbose_output(cmd: Cmd<'_>) -> xshell::Result<std::process::Output> {
+    if skip_in_dry_run(&cmd) {
+        return Ok(std::process::Output {
+            status: std::process::ExitStatus::default(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        });
+    }
+    let _watchdog = watchdog(&cmd);
+    if verbosity() > 0 {
+        eprintln!("+ {cmd}");
+    }
+    cmd.output()
+}
+
+/// Fail early with an actionable message if `gh` has no credentials to work with.
+///
+/// Otherwise every later `gh` call fails with something much more confusing.
+fn ensure_gh_authenticated(sh: &Shell, hostname: Option<&str>) -> Result<()> {
+    // a token in the environment takes precedence over anything `gh auth` knows about
+    if ["GH_TOKEN", "GITHUB_TOKEN", "GH_ENTERPRISE_TOKEN"]
+        .iter()
+        .any(|var| sh.var_os(var).is_some())
+    {
+        return Ok(());
+    }
+    let hostname = hostname
+        .map(|hostname| ["--hostname", hostname])
+        .into_iter()
+        .flatten();
+    verbose_run(
+        cmd!(sh, "gh auth status {hostname...}")
+            .quiet()
+            .ignore_stdout()
+            .ignore_stderr(),
+    )
+    .map_err(|_| anyhow!("gh is not authenticated; run 'gh auth login' first"))
+}
+
+/// Parse the first `x.y.z` version out of `--version` output, e.g. `git version 2.39.0`
+/// or `gh version 2.40.1 (2024-01-01)`. A missing patch version counts as 0.
+fn parse_version(output: &str) -> Option<[u32; 3]> {
+    output.split_whitespace().find_map(|word| {
+        let mut parts = word.split('.').map(|part| part.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        let patch = parts.next().flatten().unwrap_or_default();
+        Some([major, minor, patch])
+    })
+}
+
+/// Ensure that `tool` is installed, and that its version is at least `min_version`.
+fn ensure_tool_version(sh: &Shell, tool_name: &str, min_version: &str) -> Result<()> {
+    ensure_tool(sh, tool_name)?;
+    let min = parse_version(min_version)
+        .ok_or_else(|| anyhow!("malformed minimum version {min_version}"))?;
+    let output = verbose_read(cmd!(sh, "{tool_name} --version").quiet())
+        .with_context(|| format!("getting `{tool_name}` version"))?;
+    let first_line = output.lines().next().unwrap_or_default();
+    let version = parse_version(first_line)
+        .ok_or_else(|| anyhow!("could not find a version in `{first_line}`"))?;
+    if version < min {
+        bail!(
+            "`{tool_name}` {} is too old; at least {min_version} is required",
+            version.map(|part| part.to_string()).join(".")
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BaseFetchTags {
+    /// Tags pointing into the fetched history, as git does by default.
+    Follow,
+    /// All tags, as with `git fetch --tags`.
+    All,
+    /// No tags, as with `git fetch --no-tags`.
+    None,
+}
+
+impl BaseFetchTags {
+    fn arg(self) -> Option<&'static str> {
+        match self {
+            Self::Follow => None,
+            Self::All => Some("--tags"),
+            Self::None => Some("--no-tags"),
+        }
+    }
+}
+
+/// The `git fetch` argument for fetching a single branch, with or without its tags.
+fn branch_tags_arg(fetch_tags: bool) -> Option<&'static str> {
+    (!fetch_tags).then_some("--no-tags")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            // https://no-color.org
+            Self::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stdout().is_terminal()
+                    && std::io::stderr().is_terminal()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum StatusState {
+    Error,
+    Failure,
+    Pending,
+    Success,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OnFailure {
+    /// Abort the rebase, staying on the PR's branch.
+    Abort,
+    /// Leave the rebase in progress, to look at the conflicts.
+    Inspect,
+    /// Abort the rebase and go back to the branch which was checked out to begin with.
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ConflictStrategy {
+    /// Keep the PR's version of the file.
+    Ours,
+    /// Keep the base's version of the file.
+    Theirs,
+    /// Leave conflicts for `--on-failure` to deal with.
+    Manual,
+}
+
+impl ConflictStrategy {
+    /// The `git checkout` argument which picks the side to keep. While rebasing, "ours" is the
+    /// base being rebased onto and "theirs" the PR's commit being replayed.
+    fn checkout_arg(self) -> Option<&'static str> {
+        match self {
+            Self::Ours => Some("--theirs"),
+            Self::Theirs => Some("--ours"),
+            Self::Manual => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum AutoMergeStrategy {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+impl AutoMergeStrategy {
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Merge => "--merge",
+            Self::Squash => "--squash",
+            Self::Rebase => "--rebase",
+        }
+    }
+}
+
+/// Serialized as `"success"`, `"incomplete"` or `"fail"`, e.g. in `--progress-file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CiState {
+    Success,    // all runs successful
+    Incomplete, // at least 1 run not yet complete, but no failures
+    Fail,       // at least 1 run failed
+    /// Anything else read back, e.g. written by a newer version; treated like `Incomplete`.
+    Unknown(String),
+}
+
+impl CiState {
+    /// SGR code for printing this state with [`paint`].
+    fn color(&self) -> u8 {
+        match self {
+            Self::Success => 32,
+            Self::Incomplete | Self::Unknown(_) => 33,
+            Self::Fail => 31,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Success => "success",
+            Self::Incomplete => "incomplete",
+            Self::Fail => "fail",
+            Self::Unknown(state) => state,
+        }
+    }
+}
+
+impl serde::Serialize for CiState {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CiState {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = String::deserialize(deserializer)?;
+        Ok(match state.as_str() {
+            "success" => Self::Success,
+            "incomplete" => Self::Incomplete,
+            "fail" => Self::Fail,
+            _ => Self::Unknown(state),
+        })
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct CheckRun {
+    name: String,
+    workflow_name: String,
+    status: Option<String>,
+    conclusion: String,
+}
+
+impl CheckRun {
+    /// Whether the check run has finished, however it went.
+    fn is_terminal(&self) -> bool {
+        self.status.as_deref() == Some("COMPLETED")
+    }
+
+    fn is_successy(&self) -> bool {
+        self.is_terminal() && (self.conclusion == "SUCCESS" || self.conclusion == "SKIPPED")
+    }
+
+    fn state(&self) -> CiState {
+        match (
+            self.status.as_deref().unwrap_or_default(),
+            self.conclusion.as_str(),
+        ) {
+            ("COMPLETED", "SUCCESS" | "SKIPPED" | "NEUTRAL") => CiState::Success,
+            ("QUEUED" | "IN_PROGRESS" | "WAITING" | "REQUESTED" | "PENDING", "") => {
+                CiState::Incomplete
+            }
+            ("COMPLETED", "FAILURE" | "CANCELLED" | "TIMED_OUT" | "ACTION_REQUIRED") => {
+                CiState::Fail
+            }
+            (status, conclusion) => {
+                eprintln!(
+                    "unxpected (status, conclusion) for {} / {}: ({status}, {conclusion})",
+                    self.workflow_name, self.name
+                );
+                CiState::Fail
+            }
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "__typename")]
 enum StatusCheck {
     CheckRun(CheckRun),
-    // we don't care about the value here, but serde needs to know to deserialize _something_
-    #[allow(dead_code)]
+    /// A commit status; only its `context` and `state` are of any interest.
     StatusContext(Value),
 }
 
@@ -138,6 +1311,48 @@ impl StatusCheck {
             _ => None,
         }
     }
+
+    /// The name branch protection knows this check by.
+    fn context(&self) -> &str {
+        match self {
+            Self::CheckRun(check_run) => &check_run.name,
+            Self::StatusContext(value) => value
+                .pointer("/context")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether the check will not change state again, however it went.
+    fn is_terminal(&self) -> bool {
+        match self {
+            Self::CheckRun(check_run) => check_run.is_terminal(),
+            // `EXPECTED` and `PENDING` are still to come
+            Self::StatusContext(_) => {
+                matches!(
+                    self.status_and_conclusion().0,
+                    "SUCCESS" | "FAILURE" | "ERROR"
+                )
+            }
+        }
+    }
+
+    /// `(status, conclusion)`; commit statuses only have a state, given as the status.
+    fn status_and_conclusion(&self) -> (&str, &str) {
+        match self {
+            Self::CheckRun(check_run) => (
+                check_run.status.as_deref().unwrap_or_default(),
+                &check_run.conclusion,
+            ),
+            Self::StatusContext(value) => (
+                value
+                    .pointer("/state")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                "",
+            ),
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -146,6 +1361,18 @@ struct Status {
     base_ref_name: String,
     review_decision: String,
     status_check_rollup: Vec<StatusCheck>,
+    review_requests: Vec<ReviewRequest>,
+    /// `MERGEABLE`, `CONFLICTING`, or `UNKNOWN` while github is still working it out.
+    mergeable: String,
+}
+
+/// A user or team whose review has been requested, but who hasn't reviewed yet.
+#[derive(Debug, serde::Deserialize)]
+struct ReviewRequest {
+    /// Users have a login,
+    login: Option<String>,
+    /// and teams a name.
+    name: Option<String>,
 }
 
 impl Status {
@@ -153,368 +1380,4357 @@ impl Status {
         self.review_decision == "APPROVED"
     }
 
+    fn has_pending_reviews(&self) -> bool {
+        !self.review_requests.is_empty()
+    }
+
+    fn pending_reviewers(&self) -> impl Iterator<Item = &str> {
+        self.review_requests.iter().map(|request| {
+            request
+                .login
+                .as_deref()
+                .or(request.name.as_deref())
+                .unwrap_or("unknown reviewer")
+        })
+    }
+
     fn check_runs(&self) -> impl Iterator<Item = &CheckRun> {
         self.status_check_rollup
             .iter()
             .filter_map(StatusCheck::as_check_run)
     }
 
-    fn ci_state(&self) -> CiState {
-        let mut in_progress = false;
-        for state in self.check_runs().map(CheckRun::state) {
-            match state {
-                CiState::Success => {
-                    // no action possible yet
+    /// The names in `required` which no check run has as its name or workflow name.
+    fn missing_checks<'a>(&'a self, required: &'a [String]) -> impl Iterator<Item = &'a str> {
+        required
+            .iter()
+            .filter(|name| {
+                !self
+                    .check_runs()
+                    .any(|check_run| check_run.name == **name || check_run.workflow_name == **name)
+            })
+            .map(String::as_str)
+    }
+
+    /// A one-line description of the PR's review and CI state, e.g.
+    /// `APPROVED, CI: success (4/4 checks passed), base: main`.
+    fn summary(&self, required: &[String]) -> String {
+        let review = match self.review_decision.as_str() {
+            "APPROVED" => "APPROVED",
+            "CHANGES_REQUESTED" => "CHANGES REQUESTED",
+            _ => "NOT APPROVED",
+        };
+        let states = self.check_runs().map(CheckRun::state).collect::<Vec<_>>();
+        let count = |state| states.iter().filter(|&other| *other == state).count();
+        let missing = self.missing_checks(required).count();
+        let ci = match self.ci_state(required) {
+            CiState::Success if states.is_empty() => "no checks".to_owned(),
+            CiState::Success => format!(
+                "success ({}/{} checks passed)",
+                count(CiState::Success),
+                states.len()
+            ),
+            CiState::Incomplete => {
+                format!("{} checks running", count(CiState::Incomplete) + missing)
+            }
+            CiState::Fail => format!(
+                "failure ({}/{} checks failed)",
+                count(CiState::Fail),
+                states.len()
+            ),
+            CiState::Unknown(state) => state,
+        };
+        format!("{review}, CI: {ci}, base: {}", self.base_ref_name)
+    }
+
+    /// Checks in `required` which have not appeared yet count as incomplete.
+    fn ci_state(&self, required: &[String]) -> CiState {
+        let mut in_progress = self.missing_checks(required).next().is_some();
+        for state in self.check_runs().map(CheckRun::state) {
+            match state {
+                CiState::Success => {
+                    // no action possible yet
+                }
+                CiState::Incomplete | CiState::Unknown(_) => in_progress = true,
+                CiState::Fail => return CiState::Fail,
+            }
+        }
+        if in_progress {
+            CiState::Incomplete
+        } else {
+            CiState::Success
+        }
+    }
+}
+
+/// Failed preconditions for a merge.
+///
+/// Normally the first failure aborts the merge; under `--check-only` they are all collected
+/// and reported together at the end.
+struct Checks {
+    collect: bool,
+    failures: Vec<String>,
+}
+
+impl Checks {
+    fn new(collect: bool) -> Self {
+        Self {
+            collect,
+            failures: Vec::new(),
+        }
+    }
+
+    fn fail(&mut self, failure: String) -> Result<()> {
+        if !self.collect {
+            bail!(failure);
+        }
+        self.failures.push(failure);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        let mut message = format!("{} check(s) failed:", self.failures.len());
+        for failure in self.failures {
+            message.push_str("\n  - ");
+            message.push_str(&failure);
+        }
+        bail!(message)
+    }
+}
+
+/// Whether the merge base of `base` and `branch` is no longer `expected`, an abbreviated sha.
+fn merge_base_shifted(sh: &Shell, base: &str, branch: &str, expected: &str) -> Result<bool> {
+    let merge_base = verbose_read(cmd!(sh, "git merge-base {base} {branch}"))
+        .with_context(|| format!("finding the merge base of {base} and {branch}"))?;
+    Ok(!merge_base.starts_with(expected))
+}
+
+const MERGE_BASE_SHIFTED: &str =
+    "merge-base has shifted; the base branch has advanced, please re-validate.";
+
+/// List the merge commits in `{base}..{branch}` as `<short sha> <subject>` lines.
+fn merge_commits(sh: &Shell, base: &str, branch: &str) -> Result<String> {
+    verbose_read(cmd!(
+        sh,
+        "git log --merges '--format=%h %s' {base}..{branch}"
+    ))
+    .context("listing merge commits")
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// What to do about a rebase left in progress by a conflict.
+const CONFLICT_HINT: &str = "Resolve conflicts manually, then re-run without --on-failure inspect.";
+
+/// The paths with unresolved conflicts in the working tree.
+fn conflicted_files(sh: &Shell) -> Vec<String> {
+    verbose_read(cmd!(sh, "git diff --name-only --diff-filter=U").quiet())
+        .unwrap_or_default()
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// The commit a stopped rebase was on when one of its `--exec` steps failed, if that is why it
+/// stopped rather than a conflict.
+fn failed_exec(sh: &Shell) -> Result<Option<String>> {
+    let done = verbose_read(cmd!(sh, "git rev-parse --git-path rebase-merge/done").quiet())
+        .context("finding the rebase todo list")?;
+    let Ok(done) = std::fs::read_to_string(sh.current_dir().join(done)) else {
+        return Ok(None);
+    };
+    let last = done.lines().last().unwrap_or_default();
+    if !(last.starts_with("exec ") || last.starts_with("x ")) {
+        return Ok(None);
+    }
+    verbose_read(cmd!(sh, "git rev-parse --short HEAD"))
+        .context("finding the commit the exec step failed on")
+        .map(Some)
+}
+
+/// Whether `ancestor` is reachable from `commit`.
+fn is_ancestor(sh: &Shell, ancestor: &str, commit: &str) -> Result<bool> {
+    let output = verbose_output(
+        cmd!(sh, "git merge-base --is-ancestor {ancestor} {commit}").ignore_status(),
+    )
+    .context("checking commit ancestry")?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => bail!(
+            "checking whether {ancestor} is an ancestor of {commit}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+/// Whether a failed `git push` was turned away for lack of credentials or permissions,
+/// which waiting won't fix.
+fn is_auth_error(stderr: &str) -> bool {
+    [
+        "Authentication failed",
+        "Permission denied",
+        "could not read Username",
+        "The requested URL returned error: 403",
+    ]
+    .iter()
+    .any(|needle| stderr.contains(needle))
+}
+
+/// A random-ish factor in `[1.0, 1.0 + spread)`, so that concurrent retries don't line up.
+fn jitter(spread: f64) -> f64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    1.0 + spread * f64::from(nanos) / 1e9
+}
+
+/// The number of `fixup!`, `squash!` and `amend!` commits in `base..branch`.
+fn autosquash_commits(sh: &Shell, base: &str, branch: &str) -> Result<usize> {
+    let subjects = verbose_read(cmd!(sh, "git log --format=%s {base}..{branch}").quiet())
+        .context("listing commit subjects")?;
+    Ok(subjects
+        .lines()
+        .filter(|subject| {
+            ["fixup! ", "squash! ", "amend! "]
+                .iter()
+                .any(|prefix| subject.starts_with(prefix))
+        })
+        .count())
+}
+
+/// The number of commits reachable from `rev`.
+fn commit_count(sh: &Shell, rev: &str) -> Result<u64> {
+    let count = verbose_read(cmd!(sh, "git rev-list --count {rev}").quiet())
+        .with_context(|| format!("counting commits on {rev}"))?;
+    count
+        .parse()
+        .with_context(|| format!("parsing commit count {count:?}"))
+}
+
+/// The sha of `branch`, or of `{remote}/{branch}` when given a remote, with a hint at how to
+/// fix it if it doesn't exist.
+fn contextualize_rev_parse_error(sh: &Shell, remote: Option<&str>, branch: &str) -> Result<String> {
+    let rev = match remote {
+        Some(remote) => format!("{remote}/{branch}"),
+        None => branch.to_owned(),
+    };
+    verbose_read(cmd!(sh, "git rev-parse --verify --quiet {rev}")).map_err(|_| match remote {
+        Some(remote) => anyhow!(
+            "could not find remote tracking ref for {rev}; did you forget to 'git fetch {remote}'?"
+        ),
+        None => anyhow!(
+            "could not find local branch {branch}; did you forget to 'git checkout {branch}'?"
+        ),
+    })
+}
+
+/// Whether the local `branch` is the same commit as `{remote}/{branch}`, after fetching the
+/// latter if `fetch_first`.
+fn local_branch_matches_remote(
+    sh: &Shell,
+    remote: &str,
+    branch: &str,
+    fetch_first: bool,
+) -> Result<bool> {
+    if fetch_first {
+        verbose_run(cmd!(sh, "git fetch --no-all --no-tags {remote} {branch}"))
+            .with_context(|| format!("fetching {branch} from {remote}"))?;
+    }
+    let branch_sha = contextualize_rev_parse_error(sh, None, branch)?;
+    let remote_branch_sha = contextualize_rev_parse_error(sh, Some(remote), branch)?;
+    // unlike comparing shas, this respects grafts and replaced objects
+    Ok(is_ancestor(sh, &branch_sha, &remote_branch_sha)?
+        && is_ancestor(sh, &remote_branch_sha, &branch_sha)?)
+}
+
+struct RepoData {
+    owner_login: String,
+    name: String,
+    default_branch: String,
+    /// Open PRs from the current branch, when no branch was given on the command line.
+    open_prs: Option<Vec<PrSummary>>,
+    /// Whether branch protection on the default branch requires any CI checks, if
+    /// `--warn-no-required-checks` asked.
+    has_required_ci: Option<bool>,
+}
+
+/// The CI checks which branch protection requires on `base`.
+fn required_checks(sh: &Shell, repo_data: &RepoData, base: &str) -> Result<Vec<String>> {
+    let RepoData {
+        owner_login, name, ..
+    } = repo_data;
+    // unlike the `protection` endpoint itself, this doesn't need admin rights
+    let api_version = api_version_args();
+    let required = verbose_read(
+        cmd!(
+            sh,
+            "gh api {api_version...} repos/{owner_login}/{name}/branches/{base} --jq (.protection.required_status_checks.contexts // [])[]"
+        )
+        .quiet(),
+    )
+    .with_context(|| format!("getting required checks for {base}"))?;
+    Ok(required.lines().map(ToOwned::to_owned).collect())
+}
+
+/// The number of the one open PR from `github_ref`, a `refs/heads/` ref.
+fn pr_number_for_ref(sh: &Shell, github_ref: &str) -> Result<u64> {
+    #[derive(serde::Deserialize)]
+    struct Pr {
+        number: u64,
+    }
+
+    let branch = github_ref
+        .strip_prefix("refs/heads/")
+        .ok_or_else(|| anyhow!("{github_ref} is not a branch; expected `refs/heads/<branch>`"))?;
+    let json =
+        verbose_read(cmd!(sh, "gh pr list --head {branch} --state open --json number").quiet())
+            .with_context(|| format!("listing open PRs for {branch}"))?;
+    let prs = serde_json::from_str::<Vec<Pr>>(&json).context("parsing open PRs")?;
+    match prs.as_slice() {
+        [] => bail!("no open PR found for branch {branch}"),
+        [pr] => Ok(pr.number),
+        prs => {
+            let numbers = prs
+                .iter()
+                .map(|pr| format!("#{}", pr.number))
+                .collect::<Vec<_>>();
+            bail!(
+                "several open PRs come from {branch}: {}; pass the one to merge instead",
+                numbers.join(", ")
+            )
+        }
+    }
+}
+
+/// `owner/name` from a github remote URL, e.g. `git@github.com:owner/name.git`.
+fn repo_from_url(url: &str) -> Option<(&str, &str)> {
+    let path = url.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let mut components = path.rsplit(['/', ':']);
+    let name = components.next().filter(|name| !name.is_empty())?;
+    let owner = components.next().filter(|owner| !owner.is_empty())?;
+    Some((owner, name))
+}
+
+const REPO_DATA_QUERY: &str = "
+query($owner: String!, $name: String!, $branch: String!, $openPrs: Boolean!) {
+  repository(owner: $owner, name: $name) {
+    owner { login }
+    name
+    defaultBranchRef { name }
+    pullRequests(headRefName: $branch, states: OPEN, first: 20) @include(if: $openPrs) {
+      nodes {
+        number
+        title
+        author { login }
+        commits(last: 1) { nodes { commit { statusCheckRollup { state } } } }
+      }
+    }
+  }
+}";
+
+/// Look up `repo`, a github URL, or else the repository gh picks for the working tree.
+///
+/// With a `branch`, also list the open PRs from it, all in a single request.
+fn get_repo_data(sh: &Shell, repo: Option<&str>, branch: Option<&str>) -> Result<RepoData> {
+    let repo_fields = match repo {
+        Some(url) => {
+            let (owner, name) = repo_from_url(url)
+                .ok_or_else(|| anyhow!("{url} does not look like a github repository"))?;
+            vec![
+                "-f".to_owned(),
+                format!("owner={owner}"),
+                "-f".to_owned(),
+                format!("name={name}"),
+            ]
+        }
+        // gh fills these placeholders in from the repository it picks
+        None => ["-F", "owner={owner}", "-F", "name={repo}"]
+            .map(ToOwned::to_owned)
+            .into(),
+    };
+    let open_prs = branch.is_some().to_string();
+    let branch = branch.unwrap_or_default();
+    let json = verbose_read(
+        cmd!(
+            sh,
+            "gh api graphql {repo_fields...} -f branch={branch} -F openPrs={open_prs} -f query={REPO_DATA_QUERY}"
+        )
+        .quiet(),
+    )
+    .context("getting repo data")?;
+    let value = serde_json::from_str::<Value>(&json).context("parsing gh repo data")?;
+    let repository = value
+        .pointer("/data/repository")
+        .ok_or_else(|| anyhow!("malformed result when getting gh repo data"))?;
+    let owner_login = repository
+        .pointer("/owner/login")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("malformed result when getting gh repo owner"))?
+        .to_owned();
+    let name = repository
+        .pointer("/name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("malformed result when getting gh repo name"))?
+        .to_owned();
+    let default_branch = repository
+        .pointer("/defaultBranchRef/name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("malformed result when getting gh repo default branch"))?
+        .to_owned();
+
+    let open_prs = repository
+        .pointer("/pullRequests/nodes")
+        .and_then(Value::as_array)
+        .map(|nodes| nodes.iter().map(PrSummary::from_graphql).collect())
+        .transpose()?;
+
+    Ok(RepoData {
+        owner_login,
+        name,
+        default_branch,
+        open_prs,
+        has_required_ci: None,
+    })
+}
+
+struct RemoteGuard<'a> {
+    name: String,
+    shell: &'a Shell,
+    /// `GIT_SSH_COMMAND` for talking to the remote, from `--fork-ssh-key`.
+    ssh_command: Option<String>,
+}
+
+impl<'a> RemoteGuard<'a> {
+    fn new(shell: &'a Shell, name: String, url: &str, ssh_key: Option<&Path>) -> Result<Self> {
+        verbose_run(cmd!(
+            shell,
+            "git remote add --no-fetch --no-tags {name} {url}"
+        ))
+        .context("adding remote")?;
+        if !DRY_RUN_COMMANDS.load(Ordering::Relaxed) {
+            left_behind()
+                .remotes
+                .push((shell.current_dir(), name.clone()));
+        }
+        // git runs this through the shell
+        let ssh_command = ssh_key.map(|key| {
+            let key = key.display().to_string().replace('\'', r"'\''");
+            format!("ssh -i '{key}' -o IdentitiesOnly=yes")
+        });
+        Ok(Self {
+            name,
+            shell,
+            ssh_command,
+        })
+    }
+
+    /// Make `cmd` use the remote's SSH key, if it has one.
+    fn with_ssh<'c>(&self, cmd: Cmd<'c>) -> Cmd<'c> {
+        match &self.ssh_command {
+            Some(ssh_command) => cmd.env("GIT_SSH_COMMAND", ssh_command),
+            None => cmd,
+        }
+    }
+
+    /// Fetch `branch` from the remote, and check that it arrived.
+    fn fetch_branch(&self, branch: &str, fetch_tags: bool) -> Result<()> {
+        let Self { name, shell, .. } = self;
+        let tags = branch_tags_arg(fetch_tags);
+        verbose_run(self.with_ssh(cmd!(shell, "git fetch --no-all {tags...} {name} {branch}")))
+            .with_context(|| format!("fetching {branch} from fork remote {name}"))?;
+        if DRY_RUN_COMMANDS.load(Ordering::Relaxed) {
+            // nothing was fetched to check for
+            return Ok(());
+        }
+        verbose_run(
+            cmd!(
+                shell,
+                "git rev-parse --verify --quiet refs/remotes/{name}/{branch}"
+            )
+            .quiet()
+            .ignore_stdout(),
+        )
+        .with_context(|| format!("{branch} not found on fork remote {name}"))
+    }
+}
+
+impl Drop for RemoteGuard<'_> {
+    fn drop(&mut self) {
+        let name = &self.name;
+        let _ = verbose_run(cmd!(&self.shell, "git remote remove {name}"));
+        left_behind().remotes.retain(|(_, remote)| remote != name);
+    }
+}
+
+struct PrData<'a> {
+    fork_owner: Option<String>,
+    remote: Option<RemoteGuard<'a>>,
+    branch: String,
+    /// Filled in by whichever of `fetch_base_branch` or `poll_status` asks github first.
+    base: OnceCell<String>,
+}
+
+impl<'a> PrData<'a> {
+    /// `fork`: `(head_owner, head_repo)`
+    fn new(
+        sh: &'a Shell,
+        fork: Option<(&str, &str)>,
+        branch: &str,
+        fetch_tags: bool,
+        ssh_key: Option<&Path>,
+    ) -> Result<Self> {
+        let mut remote = None;
+        if let Some((owner, repo)) = fork {
+            let name = owner.to_owned();
+            let url_json =
+                verbose_read(cmd!(sh, "gh repo view {owner}/{repo} --json sshUrl").quiet())
+                    .context("getting foreign ssh url")?;
+            let url_value =
+                serde_json::from_str::<Value>(&url_json).context("parsing foreign ssh url")?;
+            let url = url_value
+                .pointer("/sshUrl")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("malformed foreign ssh url json"))?;
+            let guard = RemoteGuard::new(sh, name, url, ssh_key)?;
+            guard.fetch_branch(branch, fetch_tags)?;
+            remote = Some(guard);
+        }
+
+        let (fork_owner, _fork_repo) = fork.unzip();
+
+        Ok(Self {
+            fork_owner: fork_owner.map(ToOwned::to_owned),
+            remote,
+            branch: branch.to_owned(),
+            base: OnceCell::new(),
+        })
+    }
+
+    /// A branch on the current remote, when it is already known that there is a PR from it.
+    fn from_branch(sh: &'a Shell, branch: &str) -> Result<Self> {
+        // without a fork there is nothing to fetch yet
+        Self::new(sh, None, branch, false, None)
+    }
+
+    /// The PR from the checked-out branch, when nothing was given on the command line.
+    ///
+    /// Refuses the default branch, which a PR could only come from by mistake, and a branch with
+    /// no open PR. If there are several, gh picks one and the others are listed.
+    fn from_current_branch(
+        sh: &'a Shell,
+        current_branch: &str,
+        repo_data: &RepoData,
+    ) -> Result<Self> {
+        if current_branch == repo_data.default_branch {
+            bail!("on default branch; must specify the PR number or branch name to merge");
+        }
+        match repo_data.open_prs.as_deref() {
+            Some([]) => bail!("there is no open PR from {current_branch}"),
+            Some(open_prs @ [_, _, ..]) => {
+                eprintln!(
+                    "warning: several open PRs come from {current_branch}; pass a PR number to pick one other than gh's choice:"
+                );
+                for PrSummary {
+                    number,
+                    title,
+                    author,
+                    ci_state,
+                } in open_prs
+                {
+                    let ci_state = ci_state
+                        .as_ref()
+                        .map_or("no CI".into(), |state| format!("CI {}", state.as_str()));
+                    eprintln!("  #{number} {title} by {} ({ci_state})", author.login);
+                }
+            }
+            _ => {}
+        }
+        Self::from_branch(sh, current_branch)
+    }
+
+    /// Parse a branch or PR number given on the command line into `Self`
+    ///
+    /// Accepts 3 formats:
+    ///
+    /// - `<integer>`: a PR number
+    /// - `<string>`: a branch on the current remote
+    /// - `<string>:<string>`: the owner of a fork, followed by the branch on that fork
+    fn parse(
+        sh: &'a Shell,
+        branch_or_pr_number: &str,
+        repo_data: &RepoData,
+        args: &Args,
+    ) -> Result<Self> {
+        if branch_or_pr_number.parse::<u64>().is_ok() {
+            let json = head_json(sh, branch_or_pr_number, args)?;
+            let value = serde_json::from_str::<Value>(&json).context("parsing pr data")?;
+            let branch = value
+                .pointer("/headRefName")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("github did not return headRefName in {json}"))?;
+            let head_owner = value
+                .pointer("/headRepositoryOwner/login")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("malformed response getting head repository owner"))?;
+            let head_repo = value
+                .pointer("/headRepository/name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("malformed response getting head repo"))?;
+            let fork = (repo_data.owner_login != head_owner).then_some((head_owner, head_repo));
+            Self::new(
+                sh,
+                fork,
+                branch,
+                args.fetch_tags,
+                args.fork_ssh_key.as_deref(),
+            )
+        } else if let Some((fork_owner, branch)) = branch_or_pr_number.split_once(':') {
+            let json = head_json(sh, branch_or_pr_number, args)?;
+            let value = serde_json::from_str::<Value>(&json).context("parsing pr data")?;
+            let head_repo = value
+                .pointer("/headRepository/name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("malformed response getting head repo"))?;
+            Self::new(
+                sh,
+                Some((fork_owner, head_repo)),
+                branch,
+                args.fetch_tags,
+                args.fork_ssh_key.as_deref(),
+            )
+        } else {
+            Self::from_branch(sh, branch_or_pr_number)
+        }
+    }
+
+    /// The branch this PR targets, which is only fetched from github once.
+    fn fetch_base_branch(&self, sh: &Shell) -> Result<&str> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Base {
+            base_ref_name: String,
+        }
+
+        if let Some(base) = self.base.get() {
+            return Ok(base);
+        }
+        let Base { base_ref_name } = pr_view(sh, &self.qualified_branch(), "baseRefName")?;
+        Ok(self.base.get_or_init(|| base_ref_name))
+    }
+
+    /// Make `cmd`, which talks to the PR's head remote, use the fork's SSH key if it has one.
+    fn with_head_ssh<'c>(&self, cmd: Cmd<'c>) -> Cmd<'c> {
+        match &self.remote {
+            Some(remote) => remote.with_ssh(cmd),
+            None => cmd,
+        }
+    }
+
+    fn qualified_branch(&self) -> Cow<'_, str> {
+        if let Some(fork_owner) = self.fork_owner.as_deref() {
+            format!("{fork_owner}:{}", self.branch).into()
+        } else {
+            (&self.branch).into()
+        }
+    }
+}
+
+/// The `gh pr view` JSON describing the head of a PR, or the contents of `--pr-data-file`
+/// instead. Also saved to `--save-pr-data`, if given.
+fn head_json(sh: &Shell, branch_or_pr_number: &str, args: &Args) -> Result<String> {
+    if let Some(path) = &args.pr_data_file {
+        return std::fs::read_to_string(path)
+            .with_context(|| format!("reading pr data from {}", path.display()));
+    }
+    let json = verbose_read(
+        cmd!(
+            sh,
+            "gh pr view {branch_or_pr_number} --json headRefName,headRepository,headRepositoryOwner"
+        )
+        .quiet(),
+    )
+    .context("getting pr data")?;
+    if let Some(path) = &args.save_pr_data {
+        std::fs::write(path, &json)
+            .with_context(|| format!("saving pr data to {}", path.display()))?;
+    }
+    Ok(json)
+}
+
+/// Fetch the given comma-separated `--json` fields of a PR from github.
+fn pr_view<T: serde::de::DeserializeOwned>(
+    sh: &Shell,
+    qualified_branch: &str,
+    fields: &str,
+) -> Result<T> {
+    let json = verbose_read(cmd!(sh, "gh pr view {qualified_branch} --json {fields}").quiet())
+        .with_context(|| format!("getting {fields} from github"))?;
+    serde_json::from_str(&json).with_context(|| format!("parsing github {fields}"))
+}
+
+/// Add text to the top and/or bottom of a PR's description.
+fn edit_pr_body(
+    sh: &Shell,
+    qualified_branch: &str,
+    prepend: Option<&str>,
+    append: Option<&str>,
+) -> Result<()> {
+    #[derive(serde::Deserialize)]
+    struct Body {
+        body: String,
+    }
+
+    let Body { body } = pr_view(sh, qualified_branch, "body")?;
+    let body = [prepend, Some(body.trim()), append]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    verbose_run(
+        cmd!(sh, "gh pr edit {qualified_branch} --body {body}")
+            .quiet()
+            .ignore_stdout(),
+    )
+    .context("editing pr description")
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Author {
+    login: String,
+}
+
+/// Logins of everyone who has approved a PR, in the order they first did so.
+fn approvers(sh: &Shell, qualified_branch: &str) -> Result<Vec<String>> {
+    #[derive(serde::Deserialize)]
+    struct Review {
+        author: Author,
+        state: String,
+    }
+    #[derive(serde::Deserialize)]
+    struct Reviews {
+        reviews: Vec<Review>,
+    }
+
+    let Reviews { reviews } = pr_view(sh, qualified_branch, "reviews")?;
+    let mut logins = Vec::new();
+    for review in reviews {
+        if review.state == "APPROVED" && !logins.contains(&review.author.login) {
+            logins.push(review.author.login);
+        }
+    }
+    Ok(logins)
+}
+
+fn labels(sh: &Shell, qualified_branch: &str) -> Result<Vec<String>> {
+    #[derive(serde::Deserialize)]
+    struct Label {
+        name: String,
+    }
+    #[derive(serde::Deserialize)]
+    struct Labels {
+        labels: Vec<Label>,
+    }
+
+    let Labels { labels } = pr_view(sh, qualified_branch, "labels")?;
+    Ok(labels.into_iter().map(|label| label.name).collect())
+}
+
+/// `Co-authored-by:` trailer values for up to `max` distinct approving reviewers of a PR.
+fn reviewer_co_authors(sh: &Shell, qualified_branch: &str, max: usize) -> Result<Vec<String>> {
+    #[derive(serde::Deserialize)]
+    struct User {
+        id: u64,
+        login: String,
+        name: Option<String>,
+        email: Option<String>,
+    }
+
+    approvers(sh, qualified_branch)?
+        .into_iter()
+        .take(max)
+        .map(|login| {
+            let api_version = api_version_args();
+            let json = verbose_read(cmd!(sh, "gh api {api_version...} /users/{login}").quiet())
+                .with_context(|| format!("getting github user {login}"))?;
+            let User {
+                id,
+                login,
+                name,
+                email,
+            } = serde_json::from_str(&json)
+                .with_context(|| format!("parsing github user {login}"))?;
+            let email = email.unwrap_or_else(|| format!("{id}+{login}@users.noreply.github.com"));
+            let name = name.unwrap_or(login);
+            Ok(format!("{name} <{email}>"))
+        })
+        .collect()
+}
+
+/// Amend the checked-out commit to add `Co-authored-by:` trailers, skipping any already present.
+fn add_co_authors(sh: &Shell, co_authors: &[String]) -> Result<()> {
+    if co_authors.is_empty() {
+        return Ok(());
+    }
+    let trailers = co_authors
+        .iter()
+        .map(|co_author| format!("--trailer=Co-authored-by: {co_author}"));
+    let message =
+        verbose_read(cmd!(sh, "git log -1 --format=%B")).context("reading commit message")?;
+    let message = verbose_read(
+        cmd!(
+            sh,
+            "git interpret-trailers --if-exists addIfDifferent {trailers...}"
+        )
+        .stdin(message),
+    )
+    .context("adding co-author trailers")?;
+    verbose_run(cmd!(sh, "git commit --amend --quiet -F -").stdin(message))
+        .context("amending commit with co-author trailers")
+}
+
+/// What goes into a changelog entry for `--release`.
+#[derive(Debug, serde::Deserialize)]
+struct ReleaseNotes {
+    number: u64,
+    title: String,
+    body: String,
+}
+
+const DEFAULT_CHANGELOG_TEMPLATE: &str =
+    "## v{version} ({date})\n\n### {title} (#{number})\n\n{body}";
+
+const DEFAULT_FAILURE_COMMENT: &str = "Could not merge `{branch}` into `{base}`:";
+
+const DEFAULT_REPORT_TEMPLATE: &str = "Merged into `{base}` by @{merged_by} in {duration}.
+
+| | |
+|-|-|
+| rebased from | {from} |
+| rebased to | {to} |
+| force-pushed | {force_pushed} |
+| commits | {commits} |
+
+Passing checks:
+{checks}
+";
+
+/// Replace each `{name}` in `template` with its value. Unknown names are left as they are.
+fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (end, *value))
+        });
+        match value {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Insert `entry` into a changelog below its `# ` title, above any existing entries.
+fn insert_changelog_entry(changelog: &str, entry: &str) -> String {
+    let (title, rest) = if changelog.starts_with("# ") {
+        changelog.split_once('\n').unwrap_or((changelog, ""))
+    } else {
+        ("", changelog)
+    };
+    let rest = rest.trim_start();
+    let mut out = String::new();
+    if !title.is_empty() {
+        out.push_str(title);
+        out.push_str("\n\n");
+    }
+    out.push_str(entry.trim());
+    out.push('\n');
+    if !rest.is_empty() {
+        out.push('\n');
+        out.push_str(rest);
+    }
+    out
+}
+
+fn unix_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The current time of day in UTC, as `HH:MM:SS`.
+fn time_of_day() -> String {
+    let secs = unix_secs() % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Today's date in UTC, as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = unix_secs();
+    // Howard Hinnant's `civil_from_days`, for days since 1970-01-01
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Commits on `base_ref` but not on `head_ref` which were committed after the PR was last
+/// updated, as `<short sha> <subject>` lines.
+fn base_commits_since_update(
+    sh: &Shell,
+    qualified_branch: &str,
+    base_ref: &str,
+    head_ref: &str,
+) -> Result<String> {
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Updated {
+        updated_at: String,
+    }
+
+    let Updated { updated_at } = pr_view(sh, qualified_branch, "updatedAt")?;
+    verbose_read(cmd!(
+        sh,
+        "git log --since={updated_at} '--format=%h %s' {head_ref}..{base_ref}"
+    ))
+    .context("listing new commits on the base")
+}
+
+/// Commits on `base_ref` but not on `head_ref`, as `<short sha> <subject>` lines.
+fn missing_base_commits(sh: &Shell, base_ref: &str, head_ref: &str) -> Result<String> {
+    verbose_read(cmd!(sh, "git log '--format=%h %s' {head_ref}..{base_ref}"))
+        .context("listing base commits missing from the branch")
+}
+
+/// Code owners who need to approve the changes in `{base_ref}...{head_ref}` but haven't, each
+/// as `<owners> for <files>`.
+///
+/// Owners are read from `CODEOWNERS` on `base_ref`. Any one owner of a file is enough; team
+/// owners count if any member has approved. Owners given by email can't be matched to an
+/// approval.
+fn missing_code_owner_approvals(
+    sh: &Shell,
+    qualified_branch: &str,
+    base_ref: &str,
+    head_ref: &str,
+) -> Result<Vec<String>> {
+    let Some(contents) = codeowners::PATHS.iter().find_map(|path| {
+        verbose_read(
+            cmd!(sh, "git show {base_ref}:{path}")
+                .quiet()
+                .ignore_stderr(),
+        )
+        .ok()
+    }) else {
+        return Ok(Vec::new());
+    };
+    let codeowners = CodeOwners::parse(&contents);
+    let files = verbose_read(cmd!(sh, "git diff --name-only {base_ref}...{head_ref}"))
+        .context("listing changed files")?;
+    let approvers = approvers(sh, qualified_branch)?;
+
+    let mut team_members = HashMap::<&str, Vec<String>>::new();
+    let mut missing = BTreeMap::<&[String], Vec<&str>>::new();
+    for file in files.lines() {
+        let owners = codeowners.owners(file);
+        if owners.is_empty() {
+            continue;
+        }
+        let mut approved = false;
+        for owner in owners {
+            approved = match owner.strip_prefix('@') {
+                Some(team) if team.contains('/') => {
+                    if !team_members.contains_key(team) {
+                        let (org, slug) = team.split_once('/').unwrap_or_default();
+                        let api_version = api_version_args();
+                        let members = verbose_read(
+                            cmd!(
+                                sh,
+                                "gh api {api_version...} --paginate orgs/{org}/teams/{slug}/members --jq .[].login"
+                            )
+                            .quiet(),
+                        )
+                        .with_context(|| format!("listing members of {owner}"))?;
+                        team_members.insert(team, members.lines().map(ToOwned::to_owned).collect());
+                    }
+                    team_members[team]
+                        .iter()
+                        .any(|member| approvers.contains(member))
+                }
+                Some(login) => approvers
+                    .iter()
+                    .any(|approver| approver.eq_ignore_ascii_case(login)),
+                None => false,
+            };
+            if approved {
+                break;
+            }
+        }
+        if !approved {
+            missing.entry(owners).or_default().push(file);
+        }
+    }
+
+    Ok(missing
+        .into_iter()
+        .map(|(owners, files)| format!("{} for {}", owners.join(" or "), files.join(", ")))
+        .collect())
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PrSummary {
+    number: u64,
+    title: String,
+    author: Author,
+    /// Only known for PRs listed in [`RepoData::open_prs`].
+    #[serde(skip)]
+    ci_state: Option<CiState>,
+}
+
+impl PrSummary {
+    /// Read a pull request node from [`REPO_DATA_QUERY`].
+    fn from_graphql(node: &Value) -> Result<Self> {
+        let number = node
+            .pointer("/number")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("malformed result when getting open PR number"))?;
+        let title = node
+            .pointer("/title")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("malformed result when getting open PR title"))?
+            .to_owned();
+        // deleted accounts have no author
+        let login = node
+            .pointer("/author/login")
+            .and_then(Value::as_str)
+            .unwrap_or("ghost")
+            .to_owned();
+        let ci_state = node
+            .pointer("/commits/nodes/0/commit/statusCheckRollup/state")
+            .and_then(Value::as_str)
+            .and_then(|state| match state {
+                "SUCCESS" => Some(CiState::Success),
+                "PENDING" | "EXPECTED" => Some(CiState::Incomplete),
+                "FAILURE" | "ERROR" => Some(CiState::Fail),
+                _ => None,
+            });
+        Ok(Self {
+            number,
+            title,
+            author: Author { login },
+            ci_state,
+        })
+    }
+}
+
+/// The issues `body` says it closes, e.g. with `Fixes #123`, in order of first mention.
+///
+/// These are the keywords github itself recognizes, in any case, optionally followed by a colon.
+fn closing_references(body: &str) -> Vec<u64> {
+    const KEYWORDS: [&str; 9] = [
+        "close", "closes", "closed", "fix", "fixes", "fixed", "resolve", "resolves", "resolved",
+    ];
+    let words = body.split_whitespace().collect::<Vec<_>>();
+    let mut issues = Vec::new();
+    for pair in words.windows(2) {
+        let keyword = pair[0]
+            .trim_start_matches(|c: char| !c.is_alphanumeric())
+            .trim_end_matches(':')
+            .to_ascii_lowercase();
+        if !KEYWORDS.contains(&keyword.as_str()) {
+            continue;
+        }
+        let Some(number) = pair[1].strip_prefix('#') else {
+            continue;
+        };
+        let digits = number
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(number.len());
+        if let Ok(issue) = number[..digits].parse() {
+            if !issues.contains(&issue) {
+                issues.push(issue);
+            }
+        }
+    }
+    issues
+}
+
+fn notify_slack(
+    sh: &Shell,
+    webhook: &str,
+    qualified_branch: &str,
+    base: &str,
+    commit_count: &str,
+) -> Result<()> {
+    let PrSummary {
+        number,
+        title,
+        author,
+        ..
+    } = pr_view(sh, qualified_branch, "number,title,author")?;
+    let author = author.login;
+    let text = format!(
+        "Merged #{number} _{title}_ by {author}: `{qualified_branch}` → `{base}` ({commit_count} commits)"
+    );
+    let body = serde_json::json!({ "text": text }).to_string();
+    verbose_run(
+        cmd!(
+            sh,
+            "curl --fail --silent --show-error -X POST -H 'Content-Type: application/json' --data {body} {webhook}"
+        )
+        // the webhook url is a credential
+        .secret(),
+    )
+    .context("posting to slack webhook")
+}
+
+fn poll_status(sh: &Shell, pr_data: &PrData<'_>) -> Result<Status> {
+    let qualified_branch = pr_data.qualified_branch();
+    let qualified_branch = qualified_branch.as_ref();
+    let status = verbose_read(
+        cmd!(
+            sh,
+            "gh pr view {qualified_branch} --json baseRefName,reviewDecision,statusCheckRollup,reviewRequests,mergeable"
+        )
+        .quiet(),
+    )
+    .context("getting status from github")?;
+
+    let status = serde_json::from_str::<Status>(&status).context("parsing github status")?;
+    pr_data.base.get_or_init(|| status.base_ref_name.clone());
+    Ok(status)
+}
+
+/// What a merge changed, as written to `--save-state-on-success`.
+#[derive(Debug, serde::Serialize)]
+struct MergeState {
+    branch: String,
+    base: String,
+    pre_rebase_branch_sha: String,
+    post_rebase_branch_sha: String,
+    pre_push_base_sha: String,
+    post_push_base_sha: String,
+    pr_number: u64,
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+}
+
+/// Append the outputs of `--github-output` for `state` to `path`.
+///
+/// Appended rather than overwritten, as other steps may share the file.
+fn write_github_output(path: &Path, state: &MergeState) -> Result<()> {
+    use std::io::Write;
+
+    let MergeState {
+        branch,
+        base,
+        post_push_base_sha,
+        pr_number,
+        ..
+    } = state;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    write!(
+        file,
+        "merged_branch={branch}\nmerged_base={base}\nnew_base_sha={post_push_base_sha}\n\
+         pr_number={pr_number}\n"
+    )?;
+    Ok(())
+}
+
+/// Outcome of a successful merge.
+#[derive(Debug, serde::Serialize)]
+struct MergeResult {
+    branch: String,
+    base: String,
+    /// The PR's [`Status::summary`] when its checks passed, e.g.
+    /// `APPROVED, CI: success (4/4 checks passed), base: main`.
+    status: String,
+    duration_secs: f64,
+    /// Only present when `--wait-for-ci` was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ci_wait_secs: Option<f64>,
+    /// The PR was added to the merge queue rather than merged.
+    queued: bool,
+    /// Auto-merge was enabled on the PR rather than merging it.
+    auto_merge: bool,
+    /// `local_git`, `merge_queue`, `auto_merge` or `github_api`.
+    method: &'static str,
+    merged_by_local_git: bool,
+    /// Not known for PRs added to the merge queue.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commits: Option<u64>,
+    force_pushed: bool,
+    /// Only present when `--base-commit-count-before` was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base_commit_count_before_push: Option<u64>,
+    /// Only present when `--base-commit-count-after` was used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base_commit_count_after_push: Option<u64>,
+    /// Only recorded for `--save-state-on-success`.
+    #[serde(skip)]
+    state: Option<MergeState>,
+}
+
+impl MergeResult {
+    fn verb(&self) -> &'static str {
+        if self.queued {
+            "queued"
+        } else if self.auto_merge {
+            "set to auto-merge"
+        } else {
+            "merged"
+        }
+    }
+
+    /// `(json key, label, value)` rows for `--summary`.
+    fn summary(&self) -> Vec<(&'static str, &'static str, String)> {
+        let yes_no = |value| if value { "yes" } else { "no" }.to_owned();
+        let secs = |secs| format_duration(Duration::from_secs_f64(secs));
+        let mut rows = vec![
+            ("branch", "Branch", self.branch.clone()),
+            ("base", "Base", self.base.clone()),
+        ];
+        if let Some(commits) = self.commits {
+            rows.push(("commits_merged", "Commits merged", commits.to_string()));
+        }
+        rows.push(("force_pushed", "Force-pushed", yes_no(self.force_pushed)));
+        if let Some(ci_wait_secs) = self.ci_wait_secs {
+            rows.push(("ci_wait", "CI wait", secs(ci_wait_secs)));
+        }
+        rows.push(("total_time", "Total time", secs(self.duration_secs)));
+        rows.push(("status", "Status", format!("✓ {}", self.verb())));
+        rows
+    }
+
+    fn report(&self, args: &Args) -> Result<()> {
+        if args.json {
+            let mut json = serde_json::to_value(self).context("serializing merge result")?;
+            if let (true, Value::Object(object)) = (args.summary, &mut json) {
+                let summary = self
+                    .summary()
+                    .into_iter()
+                    .map(|(key, _, value)| (key.to_owned(), Value::String(value)))
+                    .collect();
+                object.insert("summary".to_owned(), Value::Object(summary));
+            }
+            let json = serde_json::to_string_pretty(&json).context("serializing merge result")?;
+            println!("{json}");
+        } else if args.summary {
+            print_table(&self.summary());
+        } else {
+            let Self {
+                branch,
+                base,
+                duration_secs,
+                ..
+            } = self;
+            let verb = self.verb();
+            println!("{verb} {branch} → {base} in {duration_secs:.1}s");
+        }
+        Ok(())
+    }
+}
+
+/// Print two-column `rows` in a box; box-drawing characters are only used on a terminal.
+fn print_table(rows: &[(&str, &str, String)]) {
+    // corners and joints are top-left, top-middle, top-right, then likewise for the bottom
+    let (horizontal, vertical, corners) = if std::io::stdout().is_terminal() {
+        ('─', '│', ['┌', '┬', '┐', '└', '┴', '┘'])
+    } else {
+        ('-', '|', ['+'; 6])
+    };
+    let label_width = rows.iter().map(|(_, label, _)| label.chars().count());
+    let label_width = label_width.max().unwrap_or_default();
+    let value_width = rows.iter().map(|(_, _, value)| value.chars().count());
+    let value_width = value_width.max().unwrap_or_default();
+    let rule = |[left, middle, right]: [char; 3]| {
+        let label = horizontal.to_string().repeat(label_width + 2);
+        let value = horizontal.to_string().repeat(value_width + 2);
+        println!("{left}{label}{middle}{value}{right}");
+    };
+
+    rule([corners[0], corners[1], corners[2]]);
+    for (_, label, value) in rows {
+        println!("{vertical} {label:<label_width$} {vertical} {value:<value_width$} {vertical}");
+    }
+    rule([corners[3], corners[4], corners[5]]);
+}
+
+/// A command-line duration in seconds: either a plain number of seconds, or a sequence of
+/// numbers with units like `2m30s`. The units are `h`, `m`, `s` and `ms`.
+#[derive(Debug, Clone, Copy)]
+struct HumanDuration(f64);
+
+impl HumanDuration {
+    fn secs(self) -> f64 {
+        self.0
+    }
+
+    fn duration(self) -> Duration {
+        Duration::from_secs_f64(self.0)
+    }
+}
+
+impl std::str::FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let secs = match s.parse::<f64>() {
+            Ok(secs) => secs,
+            Err(_) if s.is_empty() => return Err("empty duration".into()),
+            Err(_) => {
+                let mut secs = 0.0;
+                let mut rest = s;
+                while !rest.is_empty() {
+                    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+                    let unit_start = rest
+                        .find(|c| !is_number(c))
+                        .ok_or_else(|| format!("missing unit at the end of `{s}`"))?;
+                    let (number, tail) = rest.split_at(unit_start);
+                    let number = number
+                        .parse::<f64>()
+                        .map_err(|_| format!("expected a number before `{tail}` in `{s}`"))?;
+                    let (unit, tail) = tail.split_at(tail.find(is_number).unwrap_or(tail.len()));
+                    let scale = match unit {
+                        "h" => 3600.0,
+                        "m" => 60.0,
+                        "s" => 1.0,
+                        "ms" => 0.001,
+                        _ => {
+                            return Err(format!(
+                                "unknown unit `{unit}` in `{s}`; use h, m, s or ms"
+                            ))
+                        }
+                    };
+                    secs += number * scale;
+                    rest = tail;
+                }
+                secs
+            }
+        };
+        if !secs.is_finite() || secs < 0.0 {
+            return Err(format!("`{s}` is not a valid duration"));
+        }
+        Ok(Self(secs))
+    }
+}
+
+impl serde::Serialize for HumanDuration {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let whole = self.0.trunc() as u64;
+        let (hours, minutes) = (whole / 3600, whole % 3600 / 60);
+        let secs = (whole % 60) as f64 + self.0.fract();
+        if hours > 0 {
+            write!(f, "{hours}h")?;
+        }
+        if minutes > 0 {
+            write!(f, "{minutes}m")?;
+        }
+        if secs > 0.0 || whole < 60 {
+            write!(f, "{secs}s")?;
+        }
+        Ok(())
+    }
+}
+
+/// How long to wait before each attempt at something, e.g. polling or retrying.
+#[derive(Debug, Clone, Copy)]
+enum WaitStrategy {
+    NoWait,
+    FixedInterval(Duration),
+    ExponentialBackoff {
+        /// The next wait, before jitter; multiplied by `factor` after each wait, up to `max`.
+        interval: Duration,
+        factor: f64,
+        max: Duration,
+        /// Each wait is lengthened by up to this fraction of itself.
+        jitter: f64,
+    },
+}
+
+impl WaitStrategy {
+    /// A fixed wait of `interval`, which may well be nothing.
+    fn fixed(interval: HumanDuration) -> Self {
+        if interval.secs() > 0.0 {
+            Self::FixedInterval(interval.duration())
+        } else {
+            Self::NoWait
+        }
+    }
+
+    /// How long to wait this time, moving on a backoff for next time.
+    fn next_interval(&mut self) -> Duration {
+        match self {
+            Self::NoWait => Duration::ZERO,
+            Self::FixedInterval(interval) => *interval,
+            Self::ExponentialBackoff {
+                interval,
+                factor,
+                max,
+                jitter: spread,
+            } => {
+                let scaled = |by: f64| {
+                    Duration::try_from_secs_f64(interval.as_secs_f64() * by)
+                        .map_or(*max, |scaled| scaled.min(*max))
+                };
+                let this = scaled(jitter(*spread));
+                *interval = scaled(*factor);
+                this
+            }
+        }
+    }
+
+    fn wait(&mut self) {
+        let interval = self.next_interval();
+        if !interval.is_zero() {
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+/// Format a duration for humans: `12.4s`, `2m 14s` or `1h 3m`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Numbered progress through the phases of a single merge, printed to stderr.
+///
+/// Also keeps track of how long each phase took, for `--timings`.
+struct Steps {
+    enabled: bool,
+    current: usize,
+    total: usize,
+    phase: Option<(&'static str, Instant)>,
+    timings: Vec<(&'static str, Duration)>,
+}
+
+impl Steps {
+    fn new(args: &Args) -> Self {
+        // fetch, merge into base
+        let mut total = 2;
+        if args.merge_queue || args.github_merge_api {
+            // enqueue, or merge
+            total = 1;
+        } else if !args.no_rebase && !args.require_up_to_date {
+            // rebase, push branch
+            total += 2;
+        }
+        if args.wait_for_approval {
+            total += 1;
+        }
+        if args.wait_for_ci {
+            total += 1;
+        }
+        if (!args.retain_branch || args.delete_remote_branch)
+            && !args.merge_queue
+            && !args.set_auto_merge
+            && !args.github_merge_api
+        {
+            total += 1;
+        }
+        if args.release.is_some() {
+            total += 1;
+        }
+        Self {
+            // nothing past the checks happens in these modes, so there's no progress to show
+            enabled: !args.is_dry_run() && !args.check_only,
+            current: 0,
+            total,
+            phase: None,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Begin the phase called `name`, ending the previous one.
+    fn next(&mut self, name: &'static str, description: impl Display) {
+        self.end();
+        self.phase = Some((name, Instant::now()));
+        self.current += 1;
+        if self.enabled {
+            let counter = format!("[{}/{}]", self.current, self.total);
+            eprintln!("{} {description}", paint(1, counter));
+        }
+    }
+
+    /// End the current phase, if any.
+    fn end(&mut self) {
+        if let Some((name, start)) = self.phase.take() {
+            self.timings.push((name, start.elapsed()));
+        }
+    }
+
+    /// Print how long each phase took, followed by some context about what was done.
+    fn print_timings(&mut self, attributes: &[(&str, &dyn Display)]) {
+        self.end();
+        eprintln!("phase           time");
+        for (name, duration) in &self.timings {
+            eprintln!("{name:<15} {}", format_duration(*duration));
+        }
+        for (name, value) in attributes {
+            eprintln!("{name:<15} {value}");
+        }
+    }
+}
+
+/// State shared by every merge in this run.
+struct Session<'a> {
+    sh: &'a Shell,
+    args: &'a Args,
+    repo_data: &'a RepoData,
+    current_branch: &'a str,
+    hooks_dir: &'a Path,
+    progress_file: Option<&'a Path>,
+}
+
+/// The state of a merge which is waiting for CI, as written to `--progress-file`.
+#[derive(serde::Serialize)]
+struct Progress<'a> {
+    phase: &'a str,
+    /// See `Status::summary`.
+    status: String,
+    ci_state: CiState,
+    elapsed_secs: u64,
+    incomplete_checks: Vec<&'a str>,
+}
+
+impl Progress<'_> {
+    /// Replace the contents of `path` with `self`, without ever leaving it half-written.
+    fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(self).context("serializing progress")?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .with_context(|| format!("writing progress to {}", path.display()))
+    }
+}
+
+/// One poll of a PR's check runs, as appended to `--log-all-checks`.
+#[derive(serde::Serialize)]
+struct ChecksLogEntry<'a> {
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    pr: &'a str,
+    poll: usize,
+    checks: Vec<&'a CheckRun>,
+}
+
+impl ChecksLogEntry<'_> {
+    fn append(&self, path: &Path) -> Result<()> {
+        use std::io::Write;
+
+        let mut json = serde_json::to_string(self).context("serializing check runs")?;
+        json.push('\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .with_context(|| format!("logging check runs to {}", path.display()))
+    }
+}
+
+/// A PR which has passed its checks, before anything has been done to it locally.
+struct CheckedPr<'a> {
+    pr_data: PrData<'a>,
+    base: String,
+    /// [`Status::summary`] as of the checks.
+    status: String,
+    start: Instant,
+    ci_wait: Option<Duration>,
+    ci_polls: usize,
+    /// Names of the check runs which passed.
+    passed_checks: Vec<String>,
+    steps: Steps,
+}
+
+/// A PR which has passed its checks and been rebased, ready to be merged into its base.
+struct PreparedMerge<'a> {
+    pr_data: PrData<'a>,
+    base: String,
+    status: String,
+    start: Instant,
+    ci_wait: Option<Duration>,
+    ci_polls: usize,
+    passed_checks: Vec<String>,
+    /// The sha of the branch before it was rebased.
+    original_head: String,
+    head: String,
+    /// The sha of the remote base before anything was pushed to it.
+    original_base: String,
+    force_pushed: bool,
+    commit_count: String,
+    /// The number of commits on the remote base before and after pushing, when checked.
+    base_commits_before: Option<u64>,
+    base_commits_after: Option<u64>,
+    steps: Steps,
+}
+
+impl<'a> Session<'a> {
+    /// Remote to fetch from; reads and writes may go through different remotes.
+    fn fetch_remote(&self) -> &'a str {
+        self.args
+            .fetch_remote
+            .as_deref()
+            .unwrap_or(&self.args.remote)
+    }
+
+    /// Remote to push to; reads and writes may go through different remotes.
+    fn push_remote(&self) -> &'a str {
+        self.args
+            .push_remote
+            .as_deref()
+            .unwrap_or(&self.args.remote)
+    }
+
+    /// Remote to fetch the base branch from.
+    fn base_fetch_remote(&self) -> &'a str {
+        self.args
+            .base_remote
+            .as_deref()
+            .unwrap_or_else(|| self.fetch_remote())
+    }
+
+    /// Remote to push the base branch to.
+    fn base_push_remote(&self) -> &'a str {
+        self.args
+            .base_remote
+            .as_deref()
+            .unwrap_or_else(|| self.push_remote())
+    }
+
+    /// `(fetch, push)` remotes for the PR's own branch.
+    fn head_remotes<'p>(&self, pr_data: &'p PrData<'_>) -> (&'p str, &'p str)
+    where
+        'a: 'p,
+    {
+        match pr_data.remote.as_ref() {
+            Some(remote) => (remote.name.as_str(), remote.name.as_str()),
+            None => (self.fetch_remote(), self.push_remote()),
+        }
+    }
+
+    fn push_option_args(&self) -> Vec<String> {
+        self.args
+            .push_options
+            .iter()
+            .map(|option| format!("--push-option={option}"))
+            .collect()
+    }
+
+    fn depth_arg(&self) -> Option<String> {
+        let depth = self.args.fetch_depth;
+        (depth > 0).then(|| format!("--depth={depth}"))
+    }
+
+    fn base_tags_arg(&self) -> Option<&'static str> {
+        self.args
+            .base_fetch_tags
+            .unwrap_or(BaseFetchTags::Follow)
+            .arg()
+    }
+
+    fn fetch_branch(&self, pr_data: &PrData<'_>) -> Result<()> {
+        let sh = self.sh;
+        let (head_remote, _) = self.head_remotes(pr_data);
+        let branch = &pr_data.branch;
+        let depth = self.depth_arg();
+        let tags = branch_tags_arg(self.args.fetch_tags);
+        verbose_run(pr_data.with_head_ssh(cmd!(
+            sh,
+            "git fetch --no-all {tags...} {depth...} {head_remote} {branch}"
+        )))
+        .context("git fetch")
+    }
+
+    /// The PR's number, and the issues its description says it closes.
+    fn closing_references(&self, qualified_branch: &str) -> Result<(u64, Vec<u64>)> {
+        #[derive(serde::Deserialize)]
+        struct Pr {
+            number: u64,
+            body: String,
+        }
+
+        let Pr { number, body } = pr_view(self.sh, qualified_branch, "number,body")?;
+        Ok((number, closing_references(&body)))
+    }
+
+    /// Close the issues the merged PR references, for `--close-issues`. Failures are only
+    /// warnings, as the merge is done by now.
+    fn close_issues(&self, qualified_branch: &str) {
+        let sh = self.sh;
+        let (number, issues) = match self.closing_references(qualified_branch) {
+            Ok(references) => references,
+            Err(err) => {
+                eprintln!("warning: not closing issues: {err:#}");
+                return;
+            }
+        };
+        let comment = format!("Closed by merge-pr via PR #{number}");
+        for issue in issues.iter().map(u64::to_string) {
+            if let Err(err) = verbose_run(cmd!(sh, "gh issue close {issue} --comment {comment}")) {
+                eprintln!("warning: failed to close issue #{issue}: {err}");
+            }
+        }
+    }
+
+    /// Report a poll of the PR's status: summarized under `--verbose`, and appended to
+    /// `--log-all-checks` if given. Failing to log it is only worth a warning.
+    fn record_poll(&self, qualified_branch: &str, poll: usize, status: &Status) {
+        if verbosity() > 0 {
+            eprintln!(
+                "{qualified_branch}: {}",
+                status.summary(&self.args.required_ci_check)
+            );
+        }
+        let Some(path) = &self.args.log_all_checks else {
+            return;
+        };
+        let entry = ChecksLogEntry {
+            timestamp: unix_secs(),
+            pr: qualified_branch,
+            poll,
+            checks: status.check_runs().collect(),
+        };
+        if let Err(err) = entry.append(path) {
+            eprintln!("warning: {err:#}");
+        }
+    }
+
+    fn fetch_base(&self) -> Result<()> {
+        let sh = self.sh;
+        let base_remote = self.base_fetch_remote();
+        let depth = self.depth_arg();
+        let tags = self.base_tags_arg();
+        verbose_run(cmd!(sh, "git fetch {tags...} {depth...} {base_remote}"))
+            .context(format!("fetching {base_remote}"))
+    }
+
+    /// Check that a PR is approved and its CI has passed.
+    ///
+    /// Returns `None` if the checks passed but nothing should be merged, i.e. under
+    /// `--dry-run` or `--check-only`.
+    fn check(&self, branch_or_pr_number: Option<&str>) -> Result<Option<CheckedPr<'a>>> {
+        let Self {
+            sh,
+            args,
+            repo_data,
+            current_branch,
+            ..
+        } = *self;
+        let start = Instant::now();
+        let mut steps = Steps::new(args);
+
+        let pr_data = match branch_or_pr_number {
+            Some(branch_or_pr_number) => PrData::parse(sh, branch_or_pr_number, repo_data, args)?,
+            None => PrData::from_current_branch(sh, current_branch, repo_data)?,
+        };
+
+        let branch = &pr_data.branch;
+        let qualified_branch = pr_data.qualified_branch();
+        let qualified_branch = qualified_branch.as_ref();
+        let base_remote = self.base_fetch_remote();
+        let (head_remote, _) = self.head_remotes(&pr_data);
+
+        if args.preview_tree {
+            self.preview_tree(&pr_data)?;
+            return Ok(None);
+        }
+
+        // get review and current ci status
+        let mut checks = Checks::new(args.check_only);
+        if let Some(pattern) = &args.pr_title_pattern {
+            self.check_title(&mut checks, qualified_branch, pattern)?;
+        }
+        let mut status = poll_status(sh, &pr_data)?;
+        let mut polls = 1;
+        self.record_poll(qualified_branch, polls, &status);
+        if args.show_required_checks {
+            self.print_required_checks(&status, pr_data.fetch_base_branch(sh)?)?;
+            return Ok(None);
+        }
+        if args.warn_no_required_checks && args.required_ci_check.is_empty() {
+            self.warn_no_required_checks(pr_data.fetch_base_branch(sh)?);
+        }
+        let waited_too_long = |waiting_for: &str| match args.max_wait {
+            Some(max_wait) if start.elapsed().as_secs_f64() > max_wait.secs() => Err(anyhow!(
+                "{branch} was still waiting for {waiting_for} after {max_wait}; giving up"
+            )),
+            _ => Ok(()),
+        };
+        if args.wait_for_approval {
+            steps.next(
+                "wait_for_approval",
+                format!("waiting for {branch} to be approved..."),
+            );
+            let mut wait = WaitStrategy::fixed(args.approval_poll_interval);
+            while !status.is_approved() {
+                waited_too_long("approval")?;
+                wait.wait();
+                status = poll_status(sh, &pr_data)?;
+                polls += 1;
+                self.record_poll(qualified_branch, polls, &status);
+            }
+        }
+        if !status.is_approved() {
+            checks.fail(format!("{branch} has not been approved"))?;
+        }
+        // github has already tried the merge, which saves finding out from a failed rebase
+        if status.mergeable == "CONFLICTING" {
+            checks.fail(format!(
+                "{branch} conflicts with its base; rebase it manually and try again"
+            ))?;
+        }
+        let default_branch = &self.repo_data.default_branch;
+        if args.ensure_base_is_default && status.base_ref_name != *default_branch {
+            checks.fail(format!(
+                "{branch} is based on {}, not the default branch {default_branch}; pass \
+                 --allow-non-default-base to merge it anyway",
+                status.base_ref_name
+            ))?;
+        }
+        let (_, head_push_remote) = self.head_remotes(&pr_data);
+        if !args.no_verify_pr_not_updating_base
+            && status.base_ref_name == *branch
+            && head_push_remote == self.base_push_remote()
+        {
+            checks.fail(format!(
+                "{branch} on {head_push_remote} is both the head and the base of the PR; \
+                 merging it would rewrite the base. Retarget the PR to the right base"
+            ))?;
+        }
+        if args.block_on_pending_reviews && status.has_pending_reviews() {
+            let pending = status.pending_reviewers().collect::<Vec<_>>().join(", ");
+            checks.fail(format!(
+                "{branch} is still waiting on reviews from {pending}"
+            ))?;
+        }
+        if !args.pr_labels_required.is_empty() || !args.pr_labels_forbidden.is_empty() {
+            let labels = labels(sh, qualified_branch)?;
+            let has_label = |wanted: &&String| {
+                labels
+                    .iter()
+                    .any(|label| label.eq_ignore_ascii_case(wanted))
+            };
+            let missing = args
+                .pr_labels_required
+                .iter()
+                .filter(|label| !has_label(label))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                checks.fail(format!(
+                    "{branch} is missing labels: {}",
+                    missing.join(", ")
+                ))?;
+            }
+            let forbidden = args
+                .pr_labels_forbidden
+                .iter()
+                .filter(has_label)
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if !forbidden.is_empty() {
+                checks.fail(format!(
+                    "{branch} has forbidden labels: {}",
+                    forbidden.join(", ")
+                ))?;
+            }
+        }
+
+        let mut ci_wait = None;
+        let mut ci_polls = 1;
+        if args.wait_for_ci {
+            steps.next("wait_for_ci", format!("waiting for CI on {branch}..."));
+            let ci_wait_start = Instant::now();
+            // retry until success or fail
+            // the spinner's escape codes only make a mess of CI logs
+            let mut spinner = (!args.no_spinner && std::io::stdout().is_terminal() && use_color())
+                .then(|| Spinner::new(Spinners::Dots, "waiting for CI...".into()));
+            let mut wait = WaitStrategy::fixed(args.ci_poll_interval);
+            while status.ci_state(&args.required_ci_check) == CiState::Incomplete {
+                if let Err(err) = waited_too_long("CI") {
+                    if let Some(spinner) = &mut spinner {
+                        spinner.stop_with_newline();
+                    }
+                    return Err(err);
+                }
+                if let Some(path) = self.progress_file {
+                    let progress = Progress {
+                        phase: "waiting_for_ci",
+                        status: status.summary(&args.required_ci_check),
+                        ci_state: status.ci_state(&args.required_ci_check),
+                        elapsed_secs: start.elapsed().as_secs(),
+                        incomplete_checks: status
+                            .check_runs()
+                            .filter(|check_run| check_run.state() == CiState::Incomplete)
+                            .map(|check_run| check_run.name.as_str())
+                            .chain(status.missing_checks(&args.required_ci_check))
+                            .collect(),
+                    };
+                    if let Err(err) = progress.write(path) {
+                        eprintln!("warning: {err:#}");
+                    }
+                }
+                wait.wait();
+                status = poll_status(sh, &pr_data)?;
+                ci_polls += 1;
+                polls += 1;
+                self.record_poll(qualified_branch, polls, &status);
+                if spinner.is_none()
+                    && status.ci_state(&args.required_ci_check) == CiState::Incomplete
+                {
+                    let checks = &status.status_check_rollup;
+                    let done = checks.iter().filter(|check| check.is_terminal()).count();
+                    eprintln!(
+                        "[{}] CI still running (poll {ci_polls}, {done}/{} checks done)",
+                        time_of_day(),
+                        checks.len()
+                    );
+                }
+            }
+            if let Some(spinner) = &mut spinner {
+                spinner.stop_with_newline();
+            }
+            ci_wait = Some(ci_wait_start.elapsed());
+        }
+
+        if !args.ignore_ci && status.ci_state(&args.required_ci_check) != CiState::Success {
+            for non_success in status
+                .check_runs()
+                .filter(|check_run| !check_run.is_successy())
+            {
+                let state = non_success.state();
+                let CheckRun {
+                    name,
+                    workflow_name,
+                    ..
+                } = non_success;
+                let state = paint(state.color(), state.as_str());
+                println!("{workflow_name} / {name}: {state}");
+            }
+            for name in status.missing_checks(&args.required_ci_check) {
+                let state = paint(CiState::Incomplete.color(), "Missing");
+                println!("{name}: {state}");
+            }
+            checks.fail("some ci checks are incomplete or unsuccessful".into())?;
+        }
+
+        if args.is_dry_run() {
+            println!(
+                "{qualified_branch}: {}",
+                status.summary(&args.required_ci_check)
+            );
+            println!("all checks OK but aborting due to dry run");
+            if !args.pr_assignee.is_empty() {
+                println!("would assign PR to {}", self.assignees()?.join(", "));
+            }
+            if args.close_issues {
+                let (_, issues) = self.closing_references(qualified_branch)?;
+                for issue in issues {
+                    println!("would close issue #{issue}");
+                }
+            }
+            if let Some(pattern) = &args.branch_cleanup_pattern {
+                let base = pr_data.fetch_base_branch(sh)?;
+                self.clean_up_branches(pattern, base, true);
+            }
+            self.show_patches(&pr_data)?;
+            return Ok(None);
+        }
+
+        let base = pr_data.fetch_base_branch(sh)?.to_owned();
+
+        if args.check_only {
+            // fetching only moves remote-tracking refs, so the rest of the checks can be done
+            // against those without touching any local branch
+            self.fetch_branch(&pr_data)?;
+            self.fetch_base()?;
+
+            let has_local_branch = verbose_run(
+                cmd!(sh, "git rev-parse --verify --quiet refs/heads/{branch}")
+                    .quiet()
+                    .ignore_stdout(),
+            )
+            .is_ok();
+            if has_local_branch && !local_branch_matches_remote(sh, head_remote, branch, false)? {
+                checks.fail(format!(
+                    "local branch {branch} differs from remote branch {head_remote}/{branch}"
+                ))?;
+            }
+
+            if args.require_linear_history {
+                let merges = merge_commits(
+                    sh,
+                    &format!("{base_remote}/{base}"),
+                    &format!("{head_remote}/{branch}"),
+                )?;
+                if !merges.is_empty() {
+                    checks.fail(format!("{branch} contains merge commits:\n{merges}"))?;
+                }
+            }
+
+            if let Some(expected) = &args.merge_base_sha {
+                if merge_base_shifted(
+                    sh,
+                    &format!("{base_remote}/{base}"),
+                    &format!("{head_remote}/{branch}"),
+                    expected,
+                )? {
+                    checks.fail(MERGE_BASE_SHIFTED.into())?;
+                }
+            }
+
+            if args.require_rebased_on_latest {
+                let new_commits = base_commits_since_update(
+                    sh,
+                    qualified_branch,
+                    &format!("{base_remote}/{base}"),
+                    &format!("{head_remote}/{branch}"),
+                )?;
+                if !new_commits.is_empty() {
+                    checks.fail(format!(
+                        "{base} has moved on since {branch} was last updated:\n{new_commits}"
+                    ))?;
+                }
+            }
+
+            if args.require_up_to_date {
+                let missing = missing_base_commits(
+                    sh,
+                    &format!("{base_remote}/{base}"),
+                    &format!("{head_remote}/{branch}"),
+                )?;
+                if !missing.is_empty() {
+                    checks.fail(format!(
+                        "branch {branch} is not up-to-date with {base_remote}/{base}; please \
+                         rebase first. It is missing:\n{missing}"
+                    ))?;
+                }
+            }
+
+            if args.codeowners_check {
+                let missing = missing_code_owner_approvals(
+                    sh,
+                    qualified_branch,
+                    &format!("{base_remote}/{base}"),
+                    &format!("{head_remote}/{branch}"),
+                )?;
+                for missing in missing {
+                    checks.fail(format!("missing code owner approval: {missing}"))?;
+                }
+            }
+
+            checks.finish()?;
+            return Ok(None);
+        }
+
+        let passed_checks = status
+            .check_runs()
+            .filter(|check_run| check_run.is_successy())
+            .map(|check_run| check_run.name.clone())
+            .collect();
+
+        Ok(Some(CheckedPr {
+            pr_data,
+            base,
+            status: status.summary(&args.required_ci_check),
+            start,
+            ci_wait,
+            ci_polls,
+            passed_checks,
+            steps,
+        }))
+    }
+
+    /// Work out what merging the PR would produce, for `--preview-tree`, without touching any
+    /// local branch.
+    fn preview_tree(&self, pr_data: &PrData<'_>) -> Result<()> {
+        #[derive(serde::Serialize)]
+        struct Preview<'a> {
+            branch: &'a str,
+            base: &'a str,
+            tree: &'a str,
+            conflicts: Vec<&'a str>,
+            commits: Vec<&'a str>,
+        }
+
+        let Self { sh, args, .. } = *self;
+        let branch = &pr_data.branch;
+        let base = pr_data.fetch_base_branch(sh)?;
+        let (head_remote, _) = self.head_remotes(pr_data);
+        self.fetch_branch(pr_data)?;
+        self.fetch_base()?;
+        let remote_base = format!("{}/{base}", self.base_fetch_remote());
+        let remote_branch = format!("{head_remote}/{branch}");
+
+        let output = verbose_output(
+            cmd!(
+                sh,
+                "git merge-tree --write-tree --name-only --no-messages {remote_base} {remote_branch}"
+            )
+            .ignore_status(),
+        )
+        .context("computing the merged tree")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // 1 means there were conflicts, which are listed after the tree
+        if !matches!(output.status.code(), Some(0 | 1)) {
+            bail!(
+                "git merge-tree failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let mut lines = stdout.lines();
+        let tree = lines.next().unwrap_or_default();
+        let conflicts = lines.filter(|line| !line.is_empty()).collect::<Vec<_>>();
+        let commits =
+            verbose_read(cmd!(sh, "git log --oneline {remote_base}..{remote_branch}").quiet())
+                .context("listing the commits to merge")?;
+        let preview = Preview {
+            branch,
+            base,
+            tree,
+            conflicts,
+            commits: commits.lines().collect(),
+        };
+
+        if args.json {
+            let json = serde_json::to_string_pretty(&preview).context("serializing preview")?;
+            println!("{json}");
+            return Ok(());
+        }
+        println!("merging {branch} into {base} would produce tree {tree}");
+        if preview.conflicts.is_empty() {
+            println!("without conflicts");
+        } else {
+            println!("with conflicts in:");
+            for path in &preview.conflicts {
+                println!("  {path}");
+            }
+        }
+        println!("adding {} commits:", preview.commits.len());
+        for commit in &preview.commits {
+            println!("  {commit}");
+        }
+        Ok(())
+    }
+
+    /// Show the commits which merging the PR would add to its base, as patches, for a dry run.
+    fn show_patches(&self, pr_data: &PrData<'_>) -> Result<()> {
+        let Self { sh, args, .. } = *self;
+        let base = pr_data.fetch_base_branch(sh)?;
+        let (head_remote, _) = self.head_remotes(pr_data);
+        self.fetch_branch(pr_data)?;
+        self.fetch_base()?;
+        let range = format!(
+            "{}/{base}..{head_remote}/{}",
+            self.base_fetch_remote(),
+            pr_data.branch
+        );
+
+        if let Some(dir) = &args.format_patch_output {
+            let files = verbose_read(cmd!(sh, "git format-patch -o {dir} {range}").quiet())
+                .context("saving patches")?;
+            println!(
+                "saved {} patches to {}",
+                files.lines().count(),
+                dir.display()
+            );
+            return Ok(());
+        }
+        let patches = verbose_read(cmd!(sh, "git format-patch --stdout {range}").quiet())
+            .context("formatting patches")?;
+        if std::io::stdout().is_terminal()
+            && cmd!(sh, "less -FRX").stdin(&patches).quiet().run().is_ok()
+        {
+            return Ok(());
+        }
+        println!("{patches}");
+        Ok(())
+    }
+
+    /// Check the PR's title against `--pr-title-pattern`.
+    fn check_title(
+        &self,
+        checks: &mut Checks,
+        qualified_branch: &str,
+        pattern: &str,
+    ) -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Title {
+            title: String,
+        }
+
+        let regex = Regex::new(pattern)
+            .with_context(|| format!("invalid --pr-title-pattern `{pattern}`"))?;
+        let Title { title } = pr_view(self.sh, qualified_branch, "title")?;
+        if !regex.is_match(&title) {
+            checks.fail(format!(
+                "title of {qualified_branch} does not match `{pattern}`: {title:?}"
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Warn if nothing requires CI to pass before merging into `base`, for
+    /// `--warn-no-required-checks`.
+    fn warn_no_required_checks(&self, base: &str) {
+        let Self { sh, repo_data, .. } = *self;
+        let known = repo_data
+            .has_required_ci
+            .filter(|_| base == repo_data.default_branch);
+        let has_required_ci = known.or_else(|| match required_checks(sh, repo_data, base) {
+            Ok(required) => Some(!required.is_empty()),
+            Err(err) => {
+                eprintln!("warning: {err:#}");
+                None
+            }
+        });
+        if has_required_ci == Some(false) {
+            eprintln!(
+                "{} {base} does not require any CI checks, so this PR could be merged without \
+                 any CI having passed",
+                paint(33, "warning:")
+            );
+        }
+    }
+
+    /// Print each check required by `base`'s branch protection, with its state in `status`.
+    fn print_required_checks(&self, status: &Status, base: &str) -> Result<()> {
+        let Self { sh, repo_data, .. } = *self;
+        let required = required_checks(sh, repo_data, base)?;
+        if required.is_empty() {
+            println!("{base} does not require any checks");
+            return Ok(());
+        }
+
+        let rows = required
+            .iter()
+            .map(|context| {
+                let (status, conclusion) = status
+                    .status_check_rollup
+                    .iter()
+                    .find(|check| check.context() == context)
+                    .map_or(("MISSING", ""), StatusCheck::status_and_conclusion);
+                [context, status, conclusion]
+            })
+            .collect::<Vec<_>>();
+        let header = ["check", "status", "conclusion"];
+        let width = |column: usize| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or_default()
+        };
+        let (name_width, status_width) = (width(0), width(1));
+        for [context, status, conclusion] in std::iter::once(header).chain(rows) {
+            println!("{context:<name_width$}  {status:<status_width$}  {conclusion}");
+        }
+        Ok(())
+    }
+
+    /// Apply `--pr-body-prepend` and `--pr-body-append`, if given.
+    fn edit_pr_body(&self, qualified_branch: &str) -> Result<()> {
+        let args = self.args;
+        if args.pr_body_prepend.is_none() && args.pr_body_append.is_none() {
+            return Ok(());
+        }
+        edit_pr_body(
+            self.sh,
+            qualified_branch,
+            args.pr_body_prepend.as_deref(),
+            args.pr_body_append.as_deref(),
+        )
+    }
+
+    /// The logins from `--pr-assignee`, with `@me` resolved to the current `gh` user.
+    fn assignees(&self) -> Result<Vec<String>> {
+        self.args
+            .pr_assignee
+            .iter()
+            .map(|login| {
+                if login != "@me" {
+                    return Ok(login.clone());
+                }
+                let sh = self.sh;
+                let api_version = api_version_args();
+                verbose_read(cmd!(sh, "gh api {api_version...} user --jq .login").quiet())
+                    .map(|login| login.trim().to_owned())
+                    .context("getting the current github user")
+            })
+            .collect()
+    }
+
+    fn assign_pr(&self, qualified_branch: &str) -> Result<()> {
+        if self.args.pr_assignee.is_empty() {
+            return Ok(());
+        }
+        let sh = self.sh;
+        let assignees = self.assignees()?.join(",");
+        verbose_run(
+            cmd!(
+                sh,
+                "gh pr edit {qualified_branch} --add-assignee {assignees}"
+            )
+            .quiet()
+            .ignore_stdout(),
+        )
+        .with_context(|| format!("assigning PR to {assignees}"))
+    }
+
+    /// Rebase a checked PR onto `onto`, or onto its base if `onto` is not set.
+    fn prepare(&self, checked: CheckedPr<'a>, onto: Option<&str>) -> Result<PreparedMerge<'a>> {
+        let Self { sh, args, .. } = *self;
+        let CheckedPr {
+            pr_data,
+            base,
+            status,
+            start,
+            ci_wait,
+            ci_polls,
+            passed_checks,
+            mut steps,
+        } = checked;
+
+        let branch = &pr_data.branch;
+        let qualified_branch = pr_data.qualified_branch();
+        let qualified_branch = qualified_branch.as_ref();
+        let base_remote = self.base_fetch_remote();
+        let (head_remote, _) = self.head_remotes(&pr_data);
+
+        // ensure that the branch is at the tip of its base for a linear history
+        steps.next(
+            "fetch",
+            format!("fetching {head_remote}/{branch} and {base_remote}/{base}..."),
+        );
+        self.fetch_branch(&pr_data)?;
+        // try checking out a local branch
+        if verbose_run(cmd!(sh, "git checkout --no-guess {branch}")).is_err() {
+            // try checking out a remote branch
+            verbose_run(cmd!(
+                sh,
+                "git checkout --no-guess -b {branch} --track {head_remote}/{branch} --"
+            ))
+            .context("git checkout branch")?;
+        }
+
+        // Before we rebase, make sure that the state on the local branch corresponds to the one on
+        // remote. Local branch state could differ if there was already a branch that wasn't in sync
+        // with the remote. In this case we don't want to do a rebase and `push -f` as that would
+        // overwrite the remote branch and merge local state, instead of remote.
+        if !local_branch_matches_remote(sh, head_remote, branch, false)? {
+            bail!("local branch {branch} differs from remote branch {head_remote}/{branch}");
+        }
+        let original_head = contextualize_rev_parse_error(sh, None, branch)?;
+
+        self.fetch_base()?;
+        let original_base = contextualize_rev_parse_error(sh, Some(base_remote), &base)?;
+        let base_commits_before = match args.base_commit_count_before {
+            Some(expected) => {
+                let count = commit_count(sh, &format!("{base_remote}/{base}"))?;
+                if count != expected {
+                    bail!(
+                        "{base_remote}/{base} has {count} commits, not the {expected} expected by \
+                         --base-commit-count-before"
+                    );
+                }
+                Some(count)
+            }
+            None => None,
+        };
+
+        if args.require_linear_history {
+            let merges = merge_commits(sh, &format!("{base_remote}/{base}"), branch)?;
+            if !merges.is_empty() {
+                eprintln!("{merges}");
+                bail!(
+                    "{branch} contains merge commits; please rebase interactively to linearize it"
+                );
+            }
+        }
+
+        if let Some(expected) = &args.merge_base_sha {
+            if merge_base_shifted(sh, &format!("{base_remote}/{base}"), branch, expected)? {
+                bail!(MERGE_BASE_SHIFTED);
+            }
+        }
+
+        if args.require_rebased_on_latest {
+            let new_commits = base_commits_since_update(
+                sh,
+                qualified_branch,
+                &format!("{base_remote}/{base}"),
+                branch,
+            )?;
+            if !new_commits.is_empty() {
+                eprintln!("{new_commits}");
+                bail!(
+                    "{base} has moved on since {branch} was last updated; \
+                     please update the branch first"
+                );
+            }
+        }
+
+        if args.require_up_to_date {
+            let missing = missing_base_commits(sh, &format!("{base_remote}/{base}"), branch)?;
+            if !missing.is_empty() {
+                eprintln!("{missing}");
+                bail!(
+                    "branch {branch} is not up-to-date with {base_remote}/{base}; please rebase \
+                     first"
+                );
+            }
+        }
+
+        if args.codeowners_check {
+            let missing = missing_code_owner_approvals(
+                sh,
+                qualified_branch,
+                &format!("{base_remote}/{base}"),
+                branch,
+            )?;
+            if !missing.is_empty() {
+                bail!(
+                    "missing code owner approvals:\n  - {}",
+                    missing.join("\n  - ")
+                );
+            }
+        }
+
+        if args.abort_on_unverified_force_push {
+            self.fetch_branch(&pr_data)?;
+            let latest = contextualize_rev_parse_error(sh, Some(head_remote), branch)?;
+            if latest != original_head {
+                bail!(
+                    "remote branch was updated between fetch and rebase; re-run to use the latest \
+                     version"
+                );
+            }
+        }
+
+        let onto = onto.map_or_else(|| format!("{base_remote}/{base}"), ToOwned::to_owned);
+        // an up-to-date branch has nothing to rebase over
+        let rebases = !args.no_rebase && !args.require_up_to_date;
+        let autosquashes = rebases && !args.no_autosquash;
+        if !autosquashes && (args.no_autosquash || args.no_autosquash_detect) {
+            let fixups = autosquash_commits(sh, &format!("{base_remote}/{base}"), branch)?;
+            if fixups > 0 {
+                eprintln!(
+                    "warning: branch contains {fixups} fixup commits that will NOT be autosquashed"
+                );
+                if !args.yes {
+                    bail!("pass --yes to merge {branch} with its fixup commits");
+                }
+            }
+        }
+
+        // only touch the PR once every check above has passed
+        self.edit_pr_body(qualified_branch)?;
+        self.assign_pr(qualified_branch)?;
+        if let Some(context) = &args.set_pr_status {
+            self.set_status(&original_head, context)?;
+        }
+
+        let mut force_pushed = false;
+        if rebases {
+            steps.next("rebase", format!("rebasing {branch} onto {onto}..."));
+            self.rebase(&pr_data, &onto)?;
+            if args.co_author_from_pr_reviewers {
+                let co_authors = reviewer_co_authors(sh, qualified_branch, args.max_co_authors)?;
+                add_co_authors(sh, &co_authors)?;
+            }
+            // under --atomic, the branches are pushed together once every PR has merged locally
+            if !args.atomic {
+                let (_, head_push_remote) = self.head_remotes(&pr_data);
+                steps.next(
+                    "push_branch",
+                    format!("pushing rebased {branch} to {head_push_remote}..."),
+                );
+                force_pushed = self.push_branch(&pr_data)?;
+            }
+        }
+
+        let head = contextualize_rev_parse_error(sh, None, branch)?;
+        let commit_count = verbose_read(cmd!(sh, "git rev-list --count {onto}..{branch}"))
+            .context("counting commits to merge")?;
+
+        Ok(PreparedMerge {
+            pr_data,
+            base,
+            status,
+            start,
+            ci_wait,
+            ci_polls,
+            passed_checks,
+            original_head,
+            head,
+            original_base,
+            force_pushed,
+            commit_count,
+            base_commits_before,
+            base_commits_after: None,
+            steps,
+        })
+    }
+
+    /// Add a checked PR to the merge queue of its base, leaving the merge itself to GitHub.
+    fn enqueue(&self, checked: CheckedPr<'_>) -> Result<MergeResult> {
+        let Self { sh, args, .. } = *self;
+        let CheckedPr {
+            pr_data,
+            base,
+            status,
+            start,
+            ci_wait,
+            ci_polls,
+            mut steps,
+            ..
+        } = checked;
+        let branch = &pr_data.branch;
+        let qualified_branch = pr_data.qualified_branch();
+        let qualified_branch = qualified_branch.as_ref();
+
+        self.edit_pr_body(qualified_branch)?;
+        self.assign_pr(qualified_branch)?;
+
+        steps.next(
+            "enqueue",
+            format!("adding {branch} to the merge queue for {base}..."),
+        );
+        // when the base requires a merge queue, `gh pr merge` without a merge strategy enqueues
+        // the PR instead of merging it
+        verbose_run(cmd!(sh, "gh pr merge {qualified_branch}"))
+            .with_context(|| format!("adding {branch} to the merge queue; does {base} use one?"))?;
+
+        if args.timings {
+            steps.print_timings(&[("base", &base), ("ci polls", &ci_polls)]);
+        }
+
+        Ok(MergeResult {
+            branch: branch.to_owned(),
+            base,
+            status,
+            duration_secs: start.elapsed().as_secs_f64(),
+            ci_wait_secs: ci_wait.map(|ci_wait| ci_wait.as_secs_f64()),
+            queued: true,
+            auto_merge: false,
+            method: "merge_queue",
+            merged_by_local_git: false,
+            commits: None,
+            force_pushed: false,
+            base_commit_count_before_push: None,
+            base_commit_count_after_push: None,
+            state: None,
+        })
+    }
+
+    /// Have GitHub merge a checked PR, for `--github-merge-api`, leaving the local repository
+    /// alone.
+    fn merge_via_api(&self, checked: CheckedPr<'_>) -> Result<MergeResult> {
+        let Self { sh, args, .. } = *self;
+        let CheckedPr {
+            pr_data,
+            base,
+            status,
+            start,
+            ci_wait,
+            ci_polls,
+            mut steps,
+            ..
+        } = checked;
+        let branch = &pr_data.branch;
+        let qualified_branch = pr_data.qualified_branch();
+        let qualified_branch = qualified_branch.as_ref();
+        let method = args.github_pr_merge_method.flag();
+
+        self.edit_pr_body(qualified_branch)?;
+        self.assign_pr(qualified_branch)?;
+
+        steps.next(
+            "merge",
+            format!("merging {branch} into {base} through github..."),
+        );
+        verbose_run(cmd!(sh, "gh pr merge {method} {qualified_branch}"))
+            .context("merging through github")?;
+
+        if args.timings {
+            steps.print_timings(&[("base", &base), ("ci polls", &ci_polls)]);
+        }
+
+        Ok(MergeResult {
+            branch: branch.to_owned(),
+            base,
+            status,
+            duration_secs: start.elapsed().as_secs_f64(),
+            ci_wait_secs: ci_wait.map(|ci_wait| ci_wait.as_secs_f64()),
+            queued: false,
+            auto_merge: false,
+            method: "github_api",
+            merged_by_local_git: false,
+            commits: None,
+            force_pushed: false,
+            base_commit_count_before_push: None,
+            base_commit_count_after_push: None,
+            state: None,
+        })
+    }
+
+    /// Enable GitHub's auto-merge on a rebased PR, instead of pushing it to the base ourselves.
+    fn auto_merge(&self, merge: PreparedMerge<'_>) -> Result<MergeResult> {
+        let Self { sh, args, .. } = *self;
+        let PreparedMerge {
+            pr_data,
+            base,
+            status,
+            start,
+            ci_wait,
+            ci_polls,
+            force_pushed,
+            commit_count,
+            mut steps,
+            ..
+        } = merge;
+        let branch = &pr_data.branch;
+        let qualified_branch = pr_data.qualified_branch();
+        let qualified_branch = qualified_branch.as_ref();
+        let strategy = args.auto_merge_strategy.flag();
+
+        steps.next(
+            "auto_merge",
+            format!("enabling auto-merge of {branch} into {base}..."),
+        );
+        verbose_run(cmd!(sh, "gh pr merge --auto {strategy} {qualified_branch}"))
+            .context("enabling auto-merge")?;
+
+        if args.timings {
+            let force_pushed = if force_pushed { "yes" } else { "no" };
+            steps.print_timings(&[
+                ("base", &base),
+                ("commits", &commit_count),
+                ("force-pushed", &force_pushed),
+                ("ci polls", &ci_polls),
+            ]);
+        }
+
+        Ok(MergeResult {
+            branch: branch.to_owned(),
+            base,
+            status,
+            duration_secs: start.elapsed().as_secs_f64(),
+            ci_wait_secs: ci_wait.map(|ci_wait| ci_wait.as_secs_f64()),
+            queued: false,
+            auto_merge: true,
+            method: "auto_merge",
+            merged_by_local_git: false,
+            commits: commit_count.parse().ok(),
+            force_pushed,
+            base_commit_count_before_push: None,
+            base_commit_count_after_push: None,
+            state: None,
+        })
+    }
+
+    /// Rebase the PR's branch, which must be checked out, onto `onto`, then run
+    /// `--exec-after-rebase` on the result.
+    fn rebase(&self, pr_data: &PrData<'_>, onto: &str) -> Result<()> {
+        let Self { sh, args, .. } = *self;
+        let branch = &pr_data.branch;
+        let base_remote = self.base_fetch_remote();
+        let (head_remote, _) = self.head_remotes(pr_data);
+
+        let exec = args
+            .rebase_exec
+            .iter()
+            .flat_map(|command| ["--exec", command]);
+        let rebase = || {
+            let exec = exec.clone();
+            if args.no_autosquash {
+                verbose_run(cmd!(sh, "git rebase {exec...} {onto}"))
+            } else {
+                // the command is a little funky because autosquash is a noop on non-interactive rebase
+                // but of course, we want a non-interactive rebase here
+                // the solution is to pass a config which specifies a noop interactive editor
+                verbose_run(cmd!(
+                    sh,
+                    "git -c sequence.editor=: rebase -i --autosquash {exec...} {onto}"
+                ))
+            }
+        };
+        let exec_failed = |commit| {
+            self.rebase_failed(
+                format!("exec step failed for commit {commit}"),
+                "Fix the issue and run 'git rebase --continue' \
+                 or abort with 'git rebase --abort'.",
+            )
+        };
+        let mut rebase_result = rebase();
+        if rebase_result.is_err() {
+            if let Some(commit) = failed_exec(sh)? {
+                return Err(exec_failed(commit));
+            }
+        }
+        if rebase_result.is_err() && args.fetch_depth > 0 {
+            // a shallow history may not reach back to the merge base (or git may complain that
+            // the shallow file has changed); deepen it and give it one more go
+            eprintln!(
+                "warning: rebase failed after a shallow fetch; fetching full history and retrying"
+            );
+            verbose_run(cmd!(sh, "git rebase --abort")).context("aborting rebase")?;
+            let is_shallow = verbose_read(cmd!(sh, "git rev-parse --is-shallow-repository"))
+                .context("checking for a shallow repository")?;
+            let unshallow = (is_shallow == "true").then_some("--unshallow");
+            let tags = self.base_tags_arg();
+            verbose_run(cmd!(sh, "git fetch {tags...} {unshallow...} {base_remote}"))
+                .context(format!("fetching full history from {base_remote}"))?;
+            if head_remote != base_remote {
+                let tags = branch_tags_arg(args.fetch_tags);
+                verbose_run(pr_data.with_head_ssh(cmd!(
+                    sh,
+                    "git fetch --no-all {tags...} {head_remote} {branch}"
+                )))
+                .context("git fetch")?;
+            }
+            rebase_result = rebase();
+        }
+        if rebase_result.is_err() && self.resolve_generated_conflicts()? {
+            rebase_result = Ok(());
+        }
+        if rebase_result.is_err() {
+            // continuing after resolving conflicts may have run into a failing exec step
+            if let Some(commit) = failed_exec(sh)? {
+                return Err(exec_failed(commit));
+            }
+            let mut message = format!(
+                "{branch} did not cleanly rebase onto {onto}; do so manually and try again"
+            );
+            let conflicts = conflicted_files(sh);
+            if !conflicts.is_empty() {
+                let conflicts = conflicts
+                    .iter()
+                    .map(|path| format!("`{path}`"))
+                    .collect::<Vec<_>>();
+                message.push_str(&format!(" (conflicts in {})", conflicts.join(", ")));
+            }
+            return Err(self.rebase_failed(message, CONFLICT_HINT));
+        }
+
+        if args.rebase_whitespace {
+            // `--whitespace` needs the apply backend, which can't autosquash, so it gets a pass
+            // of its own; `--force-rebase` makes it rewrite commits even when already on `onto`
+            if verbose_run(cmd!(
+                sh,
+                "git rebase --whitespace=fix --force-rebase {onto}"
+            ))
+            .is_err()
+            {
+                return Err(self.rebase_failed(
+                    format!("failed to fix whitespace in {branch}; do so manually and try again"),
+                    CONFLICT_HINT,
+                ));
+            }
+        }
+
+        for command in &args.exec_after_rebase {
+            if verbose_run(cmd!(sh, "sh -c {command}")).is_err() {
+                // the rebase is already complete, so undo it by hand; the branch matched its
+                // remote before we started
+                verbose_run(cmd!(sh, "git reset --hard {head_remote}/{branch}"))
+                    .context("undoing rebase")?;
+                bail!("`{command}` failed on rebased {branch}; not merging");
+            }
+            let changes =
+                verbose_read(cmd!(sh, "git status --porcelain --untracked-files=no").quiet())
+                    .context("checking for changes")?;
+            if !changes.is_empty() {
+                eprintln!("`{command}` changed tracked files; amending them into the last commit");
+                verbose_run(cmd!(sh, "git add -u"))
+                    .and_then(|()| verbose_run(cmd!(sh, "git commit --amend --no-edit --quiet")))
+                    .context("amending changes into the last commit")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the conflicts of a stopped rebase as `--rebase-conflict-strategy` says, as long as
+    /// they are all in generated files, and continue it. Returns whether the rebase completed.
+    fn resolve_generated_conflicts(&self) -> Result<bool> {
+        let Self { sh, args, .. } = *self;
+        let Some(side) = args.rebase_conflict_strategy.checkout_arg() else {
+            return Ok(false);
+        };
+        loop {
+            let conflicts = conflicted_files(sh);
+            let generated = |path: &String| {
+                args.generated_file_pattern
+                    .iter()
+                    .any(|pattern| codeowners::matches(pattern, path))
+            };
+            if conflicts.is_empty() || !conflicts.iter().all(generated) {
+                return Ok(false);
+            }
+            eprintln!(
+                "resolving conflicts in generated files: {}",
+                conflicts.join(", ")
+            );
+            let files = &conflicts;
+            // fails if one side deleted the file, which is for a human to sort out
+            if verbose_run(cmd!(sh, "git checkout {side} -- {files...}")).is_err() {
+                return Ok(false);
+            }
+            verbose_run(cmd!(sh, "git add -- {files...}")).context("staging resolved conflicts")?;
+            if verbose_run(cmd!(sh, "git rebase --continue").env("GIT_EDITOR", "true")).is_ok() {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Clean up after a failed rebase as `--on-failure` says, and return the error to report.
+    ///
+    /// `hint` says what to do next when the rebase is left in progress.
+    fn rebase_failed(&self, message: String, hint: &str) -> anyhow::Error {
+        let Self {
+            sh, current_branch, ..
+        } = *self;
+        let abort = || verbose_run(cmd!(sh, "git rebase --abort")).context("aborting rebase");
+        let cleanup = match self.args.on_failure {
+            OnFailure::Abort => abort(),
+            OnFailure::Inspect => {
+                eprintln!("leaving the rebase in progress. {hint}");
+                Ok(())
+            }
+            // there's nothing to go back to from a detached HEAD
+            OnFailure::Reset if current_branch.is_empty() => abort(),
+            OnFailure::Reset => abort().and_then(|()| {
+                verbose_run(cmd!(sh, "git checkout {current_branch}"))
+                    .context("returning to the original branch")
+            }),
+        };
+        match cleanup {
+            Ok(()) => anyhow!(message),
+            Err(err) => err.context(message),
+        }
+    }
+
+    /// Force-push the PR's branch if the rebase moved it. Returns whether it was pushed.
+    fn push_branch(&self, pr_data: &PrData<'_>) -> Result<bool> {
+        let Self { sh, args, .. } = *self;
+        let branch = &pr_data.branch;
+        let (head_remote, head_push_remote) = self.head_remotes(pr_data);
+
+        // if rebase moved the tip then force-push to ensure github is tracking the new history
+        // this resets CI, but doesn't mess with the approvals. We can assume CI is OK, at this point
+        let force_pushed = !local_branch_matches_remote(sh, head_remote, branch, false)?;
+        if !force_pushed {
+            eprintln!("{branch} is already up to date on {head_push_remote}; not pushing it");
+        } else {
+            // the remote-tracking ref isn't moved by the rebase, and when fetching and pushing
+            // through different remotes it's the only one we have
+            let lease = contextualize_rev_parse_error(sh, Some(head_remote), branch)?;
+            let push_options = self.push_option_args();
+            let (refspec, remote_ref) = push_refspec(branch, &args.branch_refspec, "branch");
+            verbose_run(pr_data.with_head_ssh(cmd!(
+                sh,
+                "git push --force-with-lease={remote_ref}:{lease} {push_options...} {head_push_remote} {refspec}"
+            )))
+            .context("force-pushing branch")?;
+
+            // pushing only moves the remote-tracking ref of the remote pushed to
+            if head_push_remote != head_remote
+                && !DRY_RUN_COMMANDS.load(Ordering::Relaxed)
+                && !local_branch_matches_remote(sh, head_remote, branch, true)?
+            {
+                bail!(
+                    "{head_remote}/{branch} does not match {branch} after pushing it to \
+                     {head_push_remote}"
+                );
+            }
+
+            // Because we're pushing again to the remote base branch in a moment, let's wait, to let github
+            // handle this push first. This is desirable, because checks get canceled and appear as failed
+            // if we merge (and delete) the branch too quickly after updating it.
+            WaitStrategy::fixed(args.wait_after_rebase).wait();
+        }
+        Ok(force_pushed)
+    }
+
+    /// Push the rebased branches of `merges`, which have all been merged into the local base.
+    fn push_branches(&self, merges: &mut [PreparedMerge<'_>]) -> Result<()> {
+        for merge in merges {
+            let PreparedMerge {
+                pr_data,
+                original_head,
+                head,
+                force_pushed,
+                steps,
+                ..
+            } = merge;
+            if head == original_head {
+                continue;
+            }
+            let (_, head_push_remote) = self.head_remotes(pr_data);
+            steps.next(
+                "push_branch",
+                format!(
+                    "pushing rebased {} to {head_push_remote}...",
+                    pr_data.branch
+                ),
+            );
+            *force_pushed = self.push_branch(pr_data)?;
+        }
+        Ok(())
+    }
+
+    /// Fast-forward the local base branch to the prepared branch.
+    fn land(&self, merge: &mut PreparedMerge<'_>) -> Result<()> {
+        let sh = self.sh;
+        let PreparedMerge {
+            pr_data,
+            base,
+            steps,
+            ..
+        } = merge;
+        let branch = &pr_data.branch;
+        steps.next("merge", format!("merging {branch} into {base}..."));
+
+        // we can now actually merge this to main without breaking anything
+        verbose_run(cmd!(sh, "git checkout {base}")).context("checking out base")?;
+        verbose_run(cmd!(sh, "git merge {branch} --ff-only"))
+            .context("performing ff-only merge to base")?;
+        Ok(())
+    }
+
+    /// Push the local base branch, which has had all of `merges` merged into it.
+    ///
+    /// Failed pushes are retried with exponential backoff. If the remote base has moved on in
+    /// the meantime, a single merge is rebased again before retrying.
+    fn publish(&self, merges: &mut [PreparedMerge<'_>]) -> Result<()> {
+        let Self { sh, args, .. } = *self;
+        let Some(base) = merges.first().map(|merge| merge.base.clone()) else {
+            return Ok(());
+        };
+        let push_remote = self.base_push_remote();
+        let base_remote = self.base_fetch_remote();
+        self.pre_push_check(&base)?;
+
+        // in principle we can now just push; github has some magic to ensure that if you are pushing main
+        // to a commit which is at the tip of an approved pr, then it counts it as a manual merge operation
+        // and is permitted.
+        //
+        // sometimes it takes a few seconds for github to catch up, so in the event of a failure we try again
+        // a bit later.
+        let (refspec, remote_ref) = push_refspec(&base, &args.ref_format, "base");
+        let lease = args
+            .verify_remote_head
+            .as_ref()
+            .map(|sha| format!("--force-with-lease={remote_ref}:{sha}"));
+        let lease = lease.as_deref();
+        let push_options = &self.push_option_args();
+        let mut backoff = WaitStrategy::ExponentialBackoff {
+            interval: args.push_retry_interval.duration(),
+            factor: 2.0,
+            max: Duration::MAX,
+            jitter: 0.25,
+        };
+        let mut attempt = 0;
+        loop {
+            let output = verbose_output(
+                cmd!(
+                    sh,
+                    "git push {lease...} {push_options...} {push_remote} {refspec}"
+                )
+                .ignore_status(),
+            )
+            .context("pushing to base")?;
+            if output.status.success() {
+                break;
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            eprint!("{stderr}");
+            if is_auth_error(&stderr) {
+                bail!("could not authenticate pushing {base} to {push_remote}; not retrying");
+            }
+            if let Some(sha) = args
+                .verify_remote_head
+                .as_ref()
+                .filter(|_| stderr.contains("stale info"))
+            {
+                bail!(
+                    "{base} on {push_remote} is no longer at {sha}; nothing was pushed. Re-run \
+                     without --verify-remote-head to merge on top of it"
+                );
+            }
+            if stderr.contains("Operation too slow") {
+                bail!(
+                    "pushing {base} to {push_remote} timed out after {}; \
+                     use --network-timeout to wait longer",
+                    args.network_timeout
+                );
+            }
+            if attempt >= args.max_push_retries {
+                bail!(
+                    "failed to push {base} to {push_remote} after {} attempts",
+                    attempt + 1
+                );
+            }
+
+            let delay = backoff.next_interval();
+            let secs = delay.as_secs_f64();
+            if attempt == 0 {
+                println!("this is normal; retrying in {secs:.1}s");
+            } else {
+                println!(
+                    "retrying in {secs:.1}s (retry {}/{})",
+                    attempt + 1,
+                    args.max_push_retries
+                );
+            }
+            std::thread::sleep(delay);
+            attempt += 1;
+
+            // if someone else got a push in first, we have to go on top of it
+            self.fetch_base()?;
+            let remote_base = format!("{base_remote}/{base}");
+            if !is_ancestor(sh, &remote_base, &base)? {
+                let [merge] = merges else {
+                    bail!("{remote_base} moved on while merging; nothing was pushed, so re-run to merge on top of it");
+                };
+                let branch = &merge.pr_data.branch;
+                eprintln!("{remote_base} has moved on; rebasing {branch} again");
+                verbose_run(cmd!(sh, "git checkout {branch}")).context("checking out branch")?;
+                self.rebase(&merge.pr_data, &remote_base)?;
+                merge.force_pushed |= self.push_branch(&merge.pr_data)?;
+                verbose_run(cmd!(sh, "git checkout -B {base} {branch}"))
+                    .context("resetting base to the rebased branch")?;
+                self.pre_push_check(&base)?;
+            }
+        }
+
+        if let Some(expected) = args.base_commit_count_after {
+            let count = commit_count(sh, &base)?;
+            for merge in merges.iter_mut() {
+                merge.base_commits_after = Some(count);
+            }
+            if count != expected {
+                bail!(
+                    "{base} was pushed to {push_remote}, but has {count} commits rather than the \
+                     {expected} expected by --base-commit-count-after"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit a changelog entry for `merge` on top of the pushed base, tag it, and push both.
+    fn release(&self, merge: &mut PreparedMerge<'_>, version: &str) -> Result<()> {
+        let Self { sh, args, .. } = *self;
+        let PreparedMerge {
+            pr_data,
+            base,
+            steps,
+            ..
+        } = merge;
+        let branch = &pr_data.branch;
+        let version = version.trim_start_matches('v');
+        let tag = format!("v{version}");
+        let push_remote = self.base_push_remote();
+        let base_remote = self.base_fetch_remote();
+        let (refspec, remote_ref) = push_refspec(base, &args.ref_format, "base");
+        steps.next("release", format!("releasing {tag} from {base}..."));
+
+        let rollback = || {
+            format!(
+                "{base} was already pushed with {branch} merged, but the release was not. \
+                 To retry it: `git push --atomic {push_remote} {refspec} refs/tags/{tag}`. \
+                 To abandon it: `git tag -d {tag}; git reset --hard {base_remote}/{base}`"
+            )
+        };
+
+        let notes: ReleaseNotes =
+            pr_view(sh, &pr_data.qualified_branch(), "number,title,body").with_context(rollback)?;
+        let number = notes.number.to_string();
+        let date = today();
+        let template = args
+            .changelog_template
+            .as_deref()
+            .unwrap_or(DEFAULT_CHANGELOG_TEMPLATE);
+        let entry = fill_template(
+            template,
+            &[
+                ("version", version),
+                ("date", &date),
+                ("number", &number),
+                ("title", &notes.title),
+                ("body", notes.body.trim()),
+            ],
+        );
+
+        let toplevel = verbose_read(cmd!(sh, "git rev-parse --show-toplevel"))
+            .context("finding the root of the working tree")
+            .with_context(rollback)?;
+        let path = Path::new(&toplevel).join("CHANGELOG.md");
+        let changelog = match std::fs::read_to_string(&path) {
+            Ok(changelog) => changelog,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => "# Changelog\n".to_owned(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("reading {}", path.display()))
+                    .with_context(rollback)
+            }
+        };
+        if DRY_RUN_COMMANDS.load(Ordering::Relaxed) {
+            eprintln!("[DRY-RUN] add release entry to {}", path.display());
+        } else {
+            std::fs::write(&path, insert_changelog_entry(&changelog, &entry))
+                .with_context(|| format!("writing {}", path.display()))
+                .with_context(rollback)?;
+        }
+
+        // with `--verify-remote-head`, the base must still be where the merge pushed it
+        let lease = match &args.verify_remote_head {
+            Some(_) => {
+                let pushed =
+                    contextualize_rev_parse_error(sh, None, base).with_context(rollback)?;
+                Some(format!("--force-with-lease={remote_ref}:{pushed}"))
+            }
+            None => None,
+        };
+        let lease = lease.as_deref();
+        let push_options = &self.push_option_args();
+
+        // a separate commit rather than an amend: amending would rewrite the PR's own head,
+        // which github then wouldn't recognize as merged
+        let message = format!("Release {tag}");
+        verbose_run(cmd!(sh, "git add {path}"))
+            .and_then(|()| verbose_run(cmd!(sh, "git commit --quiet -m {message}")))
+            .context("committing changelog")
+            .with_context(rollback)?;
+        verbose_run(cmd!(sh, "git tag -a {tag} -m {message}"))
+            .context("tagging release")
+            .with_context(rollback)?;
+        verbose_run(cmd!(
+            sh,
+            "git push --atomic {lease...} {push_options...} {push_remote} {refspec} refs/tags/{tag}"
+        ))
+        .context("pushing release")
+        .with_context(rollback)
+    }
+
+    /// Delete the local branches matching `pattern` which are merged into the remote `base`,
+    /// for `--branch-cleanup-pattern`, or only list them if `dry_run`. Failures are only
+    /// warnings, as the merge is done by now.
+    fn clean_up_branches(&self, pattern: &str, base: &str, dry_run: bool) {
+        let sh = self.sh;
+        let remote_base = format!("{}/{base}", self.base_fetch_remote());
+        let list = |merged: &str| {
+            verbose_read(
+                cmd!(
+                    sh,
+                    "git branch --list {pattern} {merged} {remote_base} --format=%(refname:short)"
+                )
+                .quiet(),
+            )
+            .map(|branches| {
+                branches
+                    .lines()
+                    // never the base itself, should it match
+                    .filter(|branch| *branch != base)
+                    .map(ToOwned::to_owned)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let (merged, unmerged) = match list("--merged")
+            .and_then(|merged| list("--no-merged").map(|unmerged| (merged, unmerged)))
+        {
+            Ok(branches) => branches,
+            Err(err) => {
+                eprintln!("warning: failed to list branches matching {pattern}: {err}");
+                return;
+            }
+        };
+        if !unmerged.is_empty() {
+            eprintln!(
+                "keeping branches not merged into {remote_base}: {}",
+                unmerged.join(", ")
+            );
+        }
+        for branch in merged {
+            if dry_run {
+                println!("would delete merged branch {branch}");
+            } else if let Err(err) = verbose_run(cmd!(sh, "git branch -d {branch}")) {
+                eprintln!("warning: failed to delete merged branch {branch}: {err}");
+            }
+        }
+    }
+
+    /// Run `--pre-push-check`, if given, on the checked-out `base`.
+    fn pre_push_check(&self, base: &str) -> Result<()> {
+        let sh = self.sh;
+        let Some(command) = &self.args.pre_push_check else {
+            return Ok(());
+        };
+        let output = verbose_output(cmd!(sh, "sh -c {command}").ignore_status())
+            .with_context(|| format!("running `{command}`"))?;
+        if !output.status.success() {
+            eprint!("{}", String::from_utf8_lossy(&output.stdout));
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            bail!("pre-push check `{command}` failed; {base} was not pushed");
+        }
+        Ok(())
+    }
+
+    /// Run the hook called `name` from the hooks directory for `merge`, if there is one.
+    fn run_hook(&self, name: &str, merge: &PreparedMerge<'_>) -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Number {
+            number: u64,
+        }
+
+        let sh = self.sh;
+        let hook = self.hooks_dir.join(name);
+        if !is_executable(&hook) {
+            return Ok(());
+        }
+        let branch = &merge.pr_data.branch;
+        let base = &merge.base;
+        let Number { number } = pr_view(sh, &merge.pr_data.qualified_branch(), "number")?;
+        let number = number.to_string();
+        verbose_run(cmd!(sh, "{hook} {branch} {base} {number}"))
+            .with_context(|| format!("running {name} hook {}", hook.display()))
+    }
+
+    /// Post the `--set-pr-status` commit status to `sha`.
+    fn set_status(&self, sha: &str, context: &str) -> Result<()> {
+        let Self {
+            sh,
+            args,
+            repo_data,
+            ..
+        } = *self;
+        let RepoData {
+            owner_login, name, ..
+        } = repo_data;
+        let state = args
+            .status_state
+            .to_possible_value()
+            .map(|value| value.get_name().to_owned())
+            .unwrap_or_default();
+        let description = args
+            .status_description
+            .as_ref()
+            .map(|description| format!("description={description}"));
+        let description = description.iter().flat_map(|field| ["--raw-field", field]);
+        let api_version = api_version_args();
+        verbose_run(
+            cmd!(
+                sh,
+                "gh api {api_version...} --method POST repos/{owner_login}/{name}/statuses/{sha} --raw-field state={state} --raw-field context={context} {description...}"
+            )
+            .ignore_stdout(),
+        )
+        .with_context(|| format!("setting the {context} status on {sha}"))
+    }
+
+    /// Comment on a merged PR with how it was merged, for `--report-to-pr`.
+    fn report_to_pr(&self, merge: &PreparedMerge<'_>) -> Result<()> {
+        let Self { sh, args, .. } = *self;
+        let PreparedMerge {
+            pr_data,
+            base,
+            start,
+            passed_checks,
+            original_head,
+            head,
+            force_pushed,
+            commit_count,
+            ..
+        } = merge;
+        let template = match &args.report_template_file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?,
+            None => DEFAULT_REPORT_TEMPLATE.to_owned(),
+        };
+        let api_version = api_version_args();
+        let merged_by = verbose_read(cmd!(sh, "gh api {api_version...} user --jq .login").quiet())
+            .context("getting the github user")?;
+        let checks = if passed_checks.is_empty() {
+            "none".to_owned()
+        } else {
+            passed_checks
+                .iter()
+                .map(|name| format!("- {name}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let body = fill_template(
+            &template,
+            &[
+                ("branch", &pr_data.branch),
+                ("base", base),
+                ("from", original_head),
+                ("to", head),
+                ("force_pushed", if *force_pushed { "yes" } else { "no" }),
+                ("commits", commit_count),
+                ("checks", &checks),
+                ("duration", &format_duration(start.elapsed())),
+                ("merged_by", &merged_by),
+            ],
+        );
+        let qualified_branch = pr_data.qualified_branch();
+        let qualified_branch = qualified_branch.as_ref();
+        verbose_run(cmd!(sh, "gh pr comment {qualified_branch} --body-file -").stdin(body))
+            .context("commenting on the PR")
+    }
+
+    /// Clean up and notify after the base has been pushed.
+    fn finish(&self, merge: PreparedMerge<'_>) -> Result<MergeResult> {
+        let Self { sh, args, .. } = *self;
+        if args.report_to_pr {
+            if let Err(err) = self.report_to_pr(&merge) {
+                eprintln!("warning: failed to comment on the PR: {err:#}");
+            }
+        }
+        let PreparedMerge {
+            pr_data,
+            base,
+            status,
+            start,
+            ci_wait,
+            ci_polls,
+            original_head,
+            head,
+            original_base,
+            force_pushed,
+            commit_count,
+            base_commits_before,
+            base_commits_after,
+            mut steps,
+            ..
+        } = merge;
+        let branch = &pr_data.branch;
+
+        let state = if args.save_state_on_success.is_some() || args.github_output.is_some() {
+            #[derive(serde::Deserialize)]
+            struct Number {
+                number: u64,
+            }
+            let Number { number } = pr_view(sh, &pr_data.qualified_branch(), "number")?;
+            Some(MergeState {
+                branch: branch.to_owned(),
+                base: base.clone(),
+                pre_rebase_branch_sha: original_head,
+                post_rebase_branch_sha: head,
+                pre_push_base_sha: original_base,
+                post_push_base_sha: contextualize_rev_parse_error(sh, None, &base)?,
+                pr_number: number,
+                timestamp: unix_secs(),
+            })
+        } else {
+            None
+        };
+
+        let (_, head_push_remote) = self.head_remotes(&pr_data);
+        if !args.retain_branch || args.delete_remote_branch {
+            WaitStrategy::fixed(args.wait_after_push).wait();
+        }
+        match (!args.retain_branch, args.delete_remote_branch) {
+            (true, true) => steps.next(
+                "cleanup",
+                format!("deleting {branch} locally and from {head_push_remote}..."),
+            ),
+            (true, false) => steps.next("cleanup", format!("deleting local branch {branch}...")),
+            (false, true) => steps.next(
+                "cleanup",
+                format!("deleting {branch} from {head_push_remote}..."),
+            ),
+            (false, false) => {}
+        }
+        if args.delete_remote_branch {
+            // github may well have deleted it already
+            if let Err(err) = verbose_run(
+                pr_data.with_head_ssh(cmd!(sh, "git push --delete {head_push_remote} {branch}")),
+            ) {
+                eprintln!("warning: failed to delete {branch} from {head_push_remote}: {err}");
+            }
+        }
+        if !args.retain_branch {
+            WaitStrategy::fixed(args.branch_delete_delay).wait();
+            verbose_run(cmd!(sh, "git branch -D {branch}")).context("removing merged branch")?;
+        }
+        if let Some(pattern) = &args.branch_cleanup_pattern {
+            self.clean_up_branches(pattern, &base, false);
+        }
+
+        if args.close_issues {
+            self.close_issues(&pr_data.qualified_branch());
+        }
+
+        if let Some(webhook) = &args.slack_webhook {
+            let qualified_branch = pr_data.qualified_branch();
+            if let Err(err) = notify_slack(sh, webhook, &qualified_branch, &base, &commit_count) {
+                eprintln!("warning: failed to notify slack: {err:#}");
+            }
+        }
+
+        if args.timings {
+            let force_pushed = if force_pushed { "yes" } else { "no" };
+            steps.print_timings(&[
+                ("base", &base),
+                ("commits", &commit_count),
+                ("force-pushed", &force_pushed),
+                ("ci polls", &ci_polls),
+            ]);
+        }
+
+        Ok(MergeResult {
+            branch: branch.to_owned(),
+            base,
+            status,
+            duration_secs: start.elapsed().as_secs_f64(),
+            ci_wait_secs: ci_wait.map(|ci_wait| ci_wait.as_secs_f64()),
+            queued: false,
+            auto_merge: false,
+            method: "local_git",
+            merged_by_local_git: true,
+            commits: commit_count.parse().ok(),
+            force_pushed,
+            base_commit_count_before_push: base_commits_before,
+            base_commit_count_after_push: base_commits_after,
+            state,
+        })
+    }
+
+    /// Merge each PR in turn with its own settings, pushing the base after each one.
+    ///
+    /// Returns the state of each merge, for `--save-state-on-success`.
+    fn merge_each(&self, jobs: &[(Option<&str>, &Args)]) -> Result<Vec<MergeState>> {
+        let mut states = Vec::new();
+        let mut failures = Vec::new();
+        for &(target, args) in jobs {
+            let session = Session { args, ..*self };
+            match session.merge_one(target) {
+                Ok(state) => states.extend(state),
+                Err(err) if self.args.keep_going => {
+                    let target = target.unwrap_or(self.current_branch);
+                    eprintln!("warning: failed to merge {target}: {err:#}");
+                    failures.push((target, err));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if !failures.is_empty() {
+            let mut message = format!(
+                "{} of {} PR(s) failed to merge:",
+                failures.len(),
+                jobs.len()
+            );
+            for (target, err) in failures {
+                message.push_str(&format!("\n  - {target}: {err:#}"));
+            }
+            bail!(message);
+        }
+        Ok(states)
+    }
+
+    /// Merge one PR, pushing the base afterwards.
+    fn merge_one(&self, target: Option<&str>) -> Result<Option<MergeState>> {
+        let Some(checked) = self.check(target)? else {
+            return Ok(None);
+        };
+        let Some(message) = &self.args.pr_comment_on_failure else {
+            return self.merge_checked(checked);
+        };
+        let qualified_branch = checked.pr_data.qualified_branch().into_owned();
+        let (branch, base) = (checked.pr_data.branch.clone(), checked.base.clone());
+        let result = self.merge_checked(checked);
+        if let Err(err) = &result {
+            let message = message.as_deref().unwrap_or(DEFAULT_FAILURE_COMMENT);
+            let message = fill_template(message, &[("branch", &branch), ("base", &base)]);
+            let body = format!("{message}\n\n```\n{err:#}\n```\n");
+            let sh = self.sh;
+            if let Err(err) =
+                verbose_run(cmd!(sh, "gh pr comment {qualified_branch} --body-file -").stdin(body))
+            {
+                eprintln!("warning: failed to comment on the PR: {err}");
+            }
+        }
+        result
+    }
+
+    /// Merge a PR which has passed its checks.
+    fn merge_checked(&self, checked: CheckedPr<'_>) -> Result<Option<MergeState>> {
+        if self.args.merge_queue {
+            self.enqueue(checked)?.report(self.args)?;
+            return Ok(None);
+        }
+        if self.args.github_merge_api {
+            self.merge_via_api(checked)?.report(self.args)?;
+            return Ok(None);
+        }
+        let mut merge = self.prepare(checked, None)?;
+        if self.args.set_auto_merge {
+            self.auto_merge(merge)?.report(self.args)?;
+            return Ok(None);
+        }
+        self.run_hook("pre-merge", &merge)?;
+        self.land(&mut merge)?;
+        self.publish(std::slice::from_mut(&mut merge))?;
+        if let Some(version) = &self.args.release {
+            self.release(&mut merge, version)?;
+        }
+        if let Err(err) = self.run_hook("post-merge", &merge) {
+            eprintln!("warning: {err:#}");
+        }
+        let result = self.finish(merge)?;
+        result.report(self.args)?;
+        Ok(result.state)
+    }
+
+    /// Merge every PR locally, each on top of the last, and only push the base once they all have.
+    fn merge_atomically(&self, targets: &[Option<&str>]) -> Result<Vec<MergeState>> {
+        let mut merges = Vec::<PreparedMerge>::new();
+        // where the local base branch was before we touched it, so that it can be restored
+        let mut original_base = None;
+        let result = self
+            .land_all(targets, &mut merges, &mut original_base)
+            .and_then(|()| self.push_branches(&mut merges));
+
+        if let Err(err) = result {
+            let sh = self.sh;
+            if let Some((base, sha)) = original_base {
+                eprintln!("unwinding local merges to {base}");
+                verbose_run(cmd!(sh, "git checkout {base}"))
+                    .and_then(|()| verbose_run(cmd!(sh, "git reset --hard {sha}")))
+                    .context("restoring local base branch")?;
+            }
+            // branches which were pushed stay rebased, to keep matching their remotes
+            for merge in merges.iter().filter(|merge| !merge.force_pushed) {
+                let PreparedMerge {
+                    pr_data,
+                    original_head,
+                    head,
+                    ..
+                } = merge;
+                if head != original_head {
+                    let branch = &pr_data.branch;
+                    verbose_run(cmd!(sh, "git branch --force {branch} {original_head}"))
+                        .with_context(|| format!("restoring local branch {branch}"))?;
                 }
-                CiState::Incomplete => in_progress = true,
-                CiState::Fail => return CiState::Fail,
             }
+            return Err(err.context("atomic merge failed; nothing was pushed to the base"));
         }
-        if in_progress {
-            CiState::Incomplete
-        } else {
-            CiState::Success
+
+        let Some(last) = merges.last() else {
+            return Ok(Vec::new());
+        };
+        eprintln!("pushing {} merged PR(s) to {}...", merges.len(), last.base);
+        self.publish(&mut merges)?;
+        for merge in &merges {
+            if let Err(err) = self.run_hook("post-merge", merge) {
+                eprintln!("warning: {err:#}");
+            }
         }
+        let mut states = Vec::new();
+        for merge in merges {
+            let result = self.finish(merge)?;
+            result.report(self.args)?;
+            states.extend(result.state);
+        }
+        Ok(states)
     }
-}
 
-fn local_branch_matches_remote(sh: &Shell, remote: &str, branch: &str) -> Result<bool> {
-    let branch_sha = cmd!(sh, "git rev-parse {branch}")
-        .read()
-        .context("reading branch sha")?;
-    let remote_branch_sha = cmd!(sh, "git rev-parse {remote}/{branch}")
-        .read()
-        .context("reading remote branch sha")?;
-    Ok(branch_sha == remote_branch_sha)
-}
+    fn land_all(
+        &self,
+        targets: &[Option<&str>],
+        merges: &mut Vec<PreparedMerge<'a>>,
+        original_base: &mut Option<(String, String)>,
+    ) -> Result<()> {
+        let sh = self.sh;
+        for &target in targets {
+            let onto = merges.last().map(|merge| merge.pr_data.branch.as_str());
+            let Some(checked) = self.check(target)? else {
+                continue;
+            };
+            if let Some(first) = merges.first() {
+                if first.base != checked.base {
+                    bail!(
+                        "--atomic requires every PR to have the same base, but {} targets {} and {} targets {}",
+                        first.pr_data.branch,
+                        first.base,
+                        checked.pr_data.branch,
+                        checked.base,
+                    );
+                }
+            }
+            let mut merge = self.prepare(checked, onto)?;
+            if merges.is_empty() {
+                let base = &merge.base;
+                let base_remote = self.base_fetch_remote();
+                let sha = verbose_read(
+                    cmd!(sh, "git rev-parse --verify --quiet refs/heads/{base}").quiet(),
+                )
+                // if there's no local base yet, the checkout creates it from the remote
+                .or_else(|_| contextualize_rev_parse_error(sh, Some(base_remote), base))
+                .context("reading base sha")?;
+                *original_base = Some((base.clone(), sha));
+            }
 
-struct RepoData {
-    owner_login: String,
-    default_branch: String,
+            let result = self
+                .run_hook("pre-merge", &merge)
+                .and_then(|()| self.land(&mut merge));
+            // kept even if it failed to merge, so that its rebase is unwound too
+            merges.push(merge);
+            result?;
+        }
+        Ok(())
+    }
 }
 
-fn get_repo_data(sh: &Shell) -> Result<RepoData> {
-    let json = cmd!(sh, "gh repo view --json owner,name")
-        .quiet()
-        .read()
-        .context("getting repo owner name")?;
-    let value = serde_json::from_str::<Value>(&json).context("parsing gh repo data")?;
-    let owner_login = value
-        .pointer("/owner/login")
-        .and_then(Value::as_str)
-        .ok_or_else(|| anyhow!("malformed result when getting gh repo owner"))?
-        .to_owned();
-    let name = value
-        .pointer("/name")
-        .and_then(Value::as_str)
-        .ok_or_else(|| anyhow!("malformed result when getting gh repo name"))?
-        .to_owned();
-
-    let gql_query = format!("query {{ repository(owner:\"{owner_login}\", name:\"{name}\") {{ defaultBranchRef {{ name }} }} }}");
-    let json = cmd!(sh, "gh api graphql -f query={gql_query}")
-        .quiet()
-        .read()
-        .context("getting repo default branch")?;
-    let value =
-        serde_json::from_str::<Value>(&json).context("parsing gh repo default branch data")?;
-    let default_branch = value
-        .pointer("/data/repository/defaultBranchRef/name")
-        .and_then(Value::as_str)
-        .ok_or_else(|| anyhow!("malformed result when getting gh repo default branch"))?
-        .to_owned();
+fn serialize_progress_file<S: serde::Serializer>(
+    path: &Option<Option<PathBuf>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(None) => serializer.serialize_str("<temporary file>"),
+        path => serde::Serialize::serialize(&path.as_ref().map(Option::as_ref), serializer),
+    }
+}
 
-    Ok(RepoData {
-        owner_login,
-        default_branch,
-    })
+fn serialize_failure_comment<S: serde::Serializer>(
+    message: &Option<Option<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let message = message
+        .as_ref()
+        .map(|message| message.as_deref().unwrap_or(DEFAULT_FAILURE_COMMENT));
+    serde::Serialize::serialize(&message, serializer)
 }
 
-struct RemoteGuard<'a> {
-    name: String,
-    shell: &'a Shell,
+fn parse_sha(sha: &str) -> Result<String, String> {
+    if (7..=40).contains(&sha.len()) && sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(sha.to_ascii_lowercase())
+    } else {
+        Err("expected a commit sha of at least 7 hex digits".into())
+    }
 }
 
-impl<'a> RemoteGuard<'a> {
-    fn new(shell: &'a Shell, name: String, url: &str) -> Result<Self> {
-        cmd!(shell, "git remote add --no-fetch --no-tags {name} {url}")
-            .run()
-            .context("adding remote")?;
-        Ok(Self { name, shell })
+fn parse_regex(pattern: &str) -> Result<String, String> {
+    match Regex::new(pattern) {
+        Ok(_) => Ok(pattern.to_owned()),
+        Err(err) => Err(format!("invalid regular expression: {err}")),
     }
 }
 
-impl Drop for RemoteGuard<'_> {
-    fn drop(&mut self) {
-        let name = &self.name;
-        let _ = cmd!(&self.shell, "git remote remove {name}").run();
+/// Check that a `--ref-format` or `--branch-refspec` template mentions `{name}`.
+fn ref_template(template: &str, name: &str) -> Result<String, String> {
+    if template.contains(&format!("{{{name}}}")) {
+        Ok(template.to_owned())
+    } else {
+        Err(format!("expected a template containing `{{{name}}}`"))
     }
 }
 
-struct PrData<'a> {
-    fork_owner: Option<String>,
-    remote: Option<RemoteGuard<'a>>,
-    branch: String,
+/// A refspec pushing the local branch `local` to the remote ref given by `template`, in which
+/// `{name}` stands for `local`, along with that remote ref.
+fn push_refspec(local: &str, template: &str, name: &str) -> (String, String) {
+    let remote = fill_template(template, &[(name, local)]);
+    let refspec = if remote == local {
+        local.to_owned()
+    } else {
+        format!("{local}:{remote}")
+    };
+    (refspec, remote)
 }
 
-impl<'a> PrData<'a> {
-    /// `fork`: `(head_owner, head_repo)`
-    fn new(sh: &'a Shell, fork: Option<(&str, &str)>, branch: &str) -> Result<Self> {
-        let mut remote = None;
-        if let Some((owner, repo)) = fork {
-            let name = owner.to_owned();
-            let url_json = cmd!(sh, "gh repo view {owner}/{repo} --json sshUrl")
-                .quiet()
-                .read()
-                .context("getting foreign ssh url")?;
-            let url_value =
-                serde_json::from_str::<Value>(&url_json).context("parsing foreign ssh url")?;
-            let url = url_value
-                .pointer("/sshUrl")
-                .and_then(Value::as_str)
-                .ok_or_else(|| anyhow!("malformed foreign ssh url json"))?;
-            remote = Some(RemoteGuard::new(sh, name, url)?);
+fn parse_gpg_key_id(id: &str) -> Result<String, String> {
+    let hex = id.strip_prefix("0x").unwrap_or(id).replace(' ', "");
+    if matches!(hex.len(), 8 | 16 | 40) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hex)
+    } else {
+        Err("expected an 8 or 16 hex digit key ID, or a 40 hex digit fingerprint".into())
+    }
+}
+
+fn parse_git_config(setting: &str) -> Result<(String, String), String> {
+    let (key, value) = setting.split_once('=').ok_or("expected `<key>=<value>`")?;
+    // git's keys are at least `section.name`
+    match key.split_once('.') {
+        Some((section, name)) if !section.is_empty() && !name.is_empty() => {
+            Ok((key.to_owned(), value.to_owned()))
         }
+        _ => Err(format!(
+            "`{key}` is not a git config key like `section.name`"
+        )),
+    }
+}
 
-        let (fork_owner, _fork_repo) = fork.unzip();
+/// Set the flag `setting` from `config` unless it's already on. Returns whether it was.
+fn flag_from_config(setting: &mut bool, config: Option<bool>) -> bool {
+    match config {
+        Some(config) if !*setting => {
+            *setting = config;
+            true
+        }
+        _ => false,
+    }
+}
 
-        Ok(Self {
-            fork_owner: fork_owner.map(ToOwned::to_owned),
-            remote,
-            branch: branch.to_owned(),
-        })
+/// Set `setting` from `config` unless it's already set. Returns whether it was.
+fn fill_from_config<T>(setting: &mut Option<T>, config: Option<T>) -> bool {
+    if setting.is_some() || config.is_none() {
+        return false;
     }
+    *setting = config;
+    true
+}
 
-    fn from_branch(sh: &'a Shell, branch: &str) -> Result<Self> {
-        Self::new(sh, None, branch)
+impl Args {
+    /// Whether to stop once the checks have passed, as any of the dry run flags ask.
+    fn is_dry_run(&self) -> bool {
+        self.dry_run || self.dry_run_diff || self.format_patch_output.is_some()
     }
 
-    /// Parse a branch or PR number into `Self`
-    ///
-    /// Accepts 3 formats:
+    /// Fill in anything not set on the command line or in the environment from the repo config.
     ///
-    /// - `<integer>`: a PR number
-    /// - `<string>`: a branch on the current remote
-    /// - `<string>:<string>`: the owner of a fork, followed by the branch on that fork
-    fn parse(sh: &'a Shell, branch_or_pr_number: &str, repo_data: &RepoData) -> Result<Self> {
-        if branch_or_pr_number.parse::<u64>().is_ok() {
-            let number = branch_or_pr_number;
-            let json = cmd!(
-                sh,
-                "gh pr view {number} --json headRefName,headRepository,headRepositoryOwner"
-            )
-            .quiet()
-            .read()
-            .context("getting pr data")?;
-            let value = serde_json::from_str::<Value>(&json).context("parsing pr data")?;
-            let branch = value
-                .pointer("/headRefName")
-                .and_then(Value::as_str)
-                .ok_or_else(|| anyhow!("github did not return headRefName in {json}"))?;
-            let head_owner = value
-                .pointer("/headRepositoryOwner/login")
-                .and_then(Value::as_str)
-                .ok_or_else(|| anyhow!("malformed response getting head repository owner"))?;
-            let head_repo = value
-                .pointer("/headRepository/name")
-                .and_then(Value::as_str)
-                .ok_or_else(|| anyhow!("malformed response getting head repo"))?;
-            let fork = (repo_data.owner_login != head_owner).then_some((head_owner, head_repo));
-            Self::new(sh, fork, branch)
-        } else if let Some((fork_owner, branch)) = branch_or_pr_number.split_once(':') {
-            let json = cmd!(sh, "gh pr view {branch_or_pr_number} --json headRepository")
-                .quiet()
-                .read()
-                .context("getting pr data")?;
-            let value = serde_json::from_str::<Value>(&json).context("parsing pr data")?;
-            let head_repo = value
-                .pointer("/headRepository/name")
-                .and_then(Value::as_str)
-                .ok_or_else(|| anyhow!("malformed response getting head repo"))?;
-            Self::new(sh, Some((fork_owner, head_repo)), branch)
-        } else {
-            Self::from_branch(sh, branch_or_pr_number)
+    /// Flags named in `decided` were already set by a config applied earlier, which wins even
+    /// where it turned them off. Returns the names of the settings which were taken from the
+    /// config.
+    fn apply_config(
+        &mut self,
+        config: Config,
+        toplevel: &Path,
+        decided: &[&str],
+    ) -> Vec<&'static str> {
+        let Config {
+            require_linear_history,
+            ensure_base_is_default,
+            gh_config_dir,
+            gh_hostname,
+            changelog_template,
+            required_labels,
+            forbidden_labels,
+            required_ci_checks,
+            pr_title_pattern,
+            fetch_tags,
+            base_fetch_tags,
+        } = config;
+        let mut applied = Vec::new();
+        if !decided.contains(&"require_linear_history")
+            && flag_from_config(&mut self.require_linear_history, require_linear_history)
+        {
+            applied.push("require_linear_history");
+        }
+        if !decided.contains(&"ensure_base_is_default")
+            && !self.allow_non_default_base
+            && flag_from_config(&mut self.ensure_base_is_default, ensure_base_is_default)
+        {
+            applied.push("ensure_base_is_default");
+        }
+        if !decided.contains(&"fetch_tags") && flag_from_config(&mut self.fetch_tags, fetch_tags) {
+            applied.push("fetch_tags");
+        }
+        if fill_from_config(&mut self.base_fetch_tags, base_fetch_tags) {
+            applied.push("base_fetch_tags");
+        }
+        let gh_config_dir = gh_config_dir.map(|dir| toplevel.join(dir));
+        if fill_from_config(&mut self.gh_config_dir, gh_config_dir) {
+            applied.push("gh_config_dir");
+        }
+        if fill_from_config(&mut self.gh_hostname, gh_hostname) {
+            applied.push("gh_hostname");
+        }
+        if fill_from_config(&mut self.changelog_template, changelog_template) {
+            applied.push("changelog_template");
+        }
+        if !required_labels.is_empty() {
+            self.pr_labels_required.extend(required_labels);
+            applied.push("pr_labels_required");
+        }
+        if !forbidden_labels.is_empty() {
+            self.pr_labels_forbidden.extend(forbidden_labels);
+            applied.push("pr_labels_forbidden");
         }
+        if !required_ci_checks.is_empty() {
+            self.required_ci_check.extend(required_ci_checks);
+            applied.push("required_ci_check");
+        }
+        if fill_from_config(&mut self.pr_title_pattern, pr_title_pattern) {
+            applied.push("pr_title_pattern");
+        }
+        applied
     }
 
-    fn qualified_branch(&self) -> Cow<'_, str> {
-        if let Some(fork_owner) = self.fork_owner.as_deref() {
-            format!("{fork_owner}:{}", self.branch).into()
-        } else {
-            (&self.branch).into()
+    /// Print the settings in effect as TOML, noting where each of them came from.
+    fn print_config(
+        &self,
+        matches: &ArgMatches,
+        from_config: &[&str],
+        from_profile: &[&str],
+    ) -> Result<()> {
+        let settings = serde_json::to_value(self).context("serializing settings")?;
+        let Value::Object(settings) = settings else {
+            bail!("settings did not serialize to an object");
+        };
+        for (name, value) in settings {
+            // lists are added to by every source, so they can come from several at once
+            let mut sources = Vec::new();
+            match matches.value_source(&name) {
+                Some(ValueSource::CommandLine) => sources.push("CLI"),
+                Some(ValueSource::EnvVariable) => sources.push("env"),
+                _ => {}
+            }
+            if from_config.contains(&name.as_str()) {
+                sources.push("file");
+            }
+            if from_profile.contains(&name.as_str()) {
+                sources.push("profile");
+            }
+            let source = match sources.as_slice() {
+                [] => "default".to_owned(),
+                sources => sources.join(" + "),
+            };
+            match (name.as_str(), value) {
+                (_, Value::Null) => println!("# {name} is not set [{source}]"),
+                ("slack_webhook", _) => println!("{name} = \"<redacted>\" # [{source}]"),
+                (_, value) => println!("{name} = {value} # [{source}]"),
+            }
         }
+        Ok(())
     }
 }
 
-fn poll_status(sh: &Shell, qualified_branch: &str) -> Result<Status> {
-    let status = cmd!(
-        sh,
-        "gh pr view {qualified_branch} --json baseRefName,reviewDecision,statusCheckRollup"
-    )
-    .quiet()
-    .read()
-    .context("getting status from github")?;
-
-    let status = serde_json::from_str::<Status>(&status).context("parsing github status")?;
-    Ok(status)
-}
-
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if args.list_profiles {
+        for name in config::profiles() {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+    VERBOSITY.store(args.verbose, Ordering::Relaxed);
+    DRY_RUN_COMMANDS.store(args.dry_run_commands, Ordering::Relaxed);
+    if args.no_color {
+        args.color = ColorChoice::Never;
+    }
+    COLOR.store(args.color.enabled(), Ordering::Relaxed);
+    if let Some(version) = &args.github_api_version {
+        GITHUB_API_VERSION.get_or_init(|| version.clone());
+    }
+    if let Some(timeout) = args.timeout_per_step {
+        STEP_TIMEOUT.get_or_init(|| timeout);
+    }
+    // read this before changing into `--workdir`, so that relative paths mean what the user expects
+    if let Some(path) = &args.pr_body_file {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        args.pr_body_append = Some(text.trim_end().to_owned());
+    }
+    for path in [
+        &mut args.pr_data_file,
+        &mut args.save_pr_data,
+        &mut args.report_template_file,
+        &mut args.save_state_on_success,
+        &mut args.github_output,
+        &mut args.log_all_checks,
+        &mut args.batch_file,
+        &mut args.hooks_dir,
+        &mut args.format_patch_output,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *path =
+            std::path::absolute(&path).with_context(|| format!("resolving {}", path.display()))?;
+    }
+    if let Some(Some(path)) = &mut args.progress_file {
+        *path = std::path::absolute(&path)
+            .with_context(|| format!("resolving progress file {}", path.display()))?;
+    }
+    if let Some(dir) = &mut args.gh_config_dir {
+        *dir = std::path::absolute(&dir)
+            .with_context(|| format!("resolving gh config dir {}", dir.display()))?;
+    }
     let sh = Shell::new()?;
-    ensure_tool(&sh, "git")?;
-    ensure_tool(&sh, "gh")?;
+    if let Some(workdir) = &args.workdir {
+        let workdir = std::fs::canonicalize(workdir)
+            .with_context(|| format!("resolving workdir {}", workdir.display()))?;
+        if !workdir.is_dir() {
+            bail!("workdir {} is not a directory", workdir.display());
+        }
+        if !workdir.join(".git").exists() {
+            bail!(
+                "workdir {} is not the root of a git repository",
+                workdir.display()
+            );
+        }
+        sh.change_dir(workdir);
+    }
+    let toplevel = verbose_read(cmd!(sh, "git rev-parse --show-toplevel").quiet())
+        .context("finding the root of the working tree")?;
+    let toplevel = Path::new(&toplevel);
+    let from_config = args.apply_config(Config::load(toplevel)?, toplevel, &[]);
+    // applied second, so the repository's own config wins
+    let from_profile = match &args.profile {
+        Some(name) => {
+            let profile = Config::load_profile(name)?;
+            args.apply_config(profile, toplevel, &from_config)
+        }
+        None => Vec::new(),
+    };
+    if args.print_config {
+        return args.print_config(&matches, &from_config, &from_profile);
+    }
 
-    let current_branch = cmd!(sh, "git branch --show-current")
-        .quiet()
-        .read()
+    // `git branch --show-current`
+    ensure_tool_version(&sh, "git", "2.22")?;
+    // `gh pr view --json`
+    ensure_tool_version(&sh, "gh", "2.0")?;
+    if args.slack_webhook.is_some() {
+        ensure_tool(&sh, "curl")?;
+    }
+
+    if args.keep_going && matches!(args.on_failure, OnFailure::Inspect) {
+        bail!(
+            "--keep-going cannot carry on past a rebase left in progress by --on-failure inspect"
+        );
+    }
+    if args.retain_branch && args.delete_remote_branch {
+        eprintln!(
+            "warning: --retain-branch with --delete-remote-branch deletes the branch from the \
+             remote but keeps it locally"
+        );
+        if !args.yes {
+            bail!("pass --yes to confirm deleting only the remote branch");
+        }
+    }
+    if args.gpg_key_id.is_some() && !args.gpg_sign {
+        eprintln!("warning: --gpg-key-id has no effect without --gpg-sign");
+    }
+    let mut config = Vec::new();
+    if args.gpg_sign {
+        config.push(("commit.gpgSign", "true"));
+        if let Some(id) = &args.gpg_key_id {
+            config.push(("user.signingKey", id));
+        }
+    }
+    // last, so that they win over the above
+    config.extend(
+        args.gitconfig
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    if args.preview_tree {
+        // `git merge-tree --write-tree`
+        ensure_tool_version(&sh, "git", "2.38")?;
+    }
+    if !config.is_empty() {
+        // `GIT_CONFIG_COUNT`
+        ensure_tool_version(&sh, "git", "2.31")?;
+        // applies to every git command run from here on, without touching anyone's config
+        sh.set_var("GIT_CONFIG_COUNT", config.len().to_string());
+        for (idx, (key, value)) in config.into_iter().enumerate() {
+            sh.set_var(format!("GIT_CONFIG_KEY_{idx}"), key);
+            sh.set_var(format!("GIT_CONFIG_VALUE_{idx}"), value);
+        }
+    }
+
+    if let Some(dir) = &args.gh_config_dir {
+        sh.set_var("GH_CONFIG_DIR", dir);
+    }
+    if let Some(hostname) = &args.gh_hostname {
+        sh.set_var("GH_HOST", hostname);
+    }
+    if matches.value_source("network_timeout") != Some(ValueSource::DefaultValue)
+        || sh.var_os("GIT_HTTP_LOW_SPEED_TIME").is_none()
+    {
+        // git gives up when a transfer stays below the limit (in bytes/s) for the time
+        let secs = args.network_timeout.secs().ceil().max(1.0) as u64;
+        sh.set_var("GIT_HTTP_LOW_SPEED_LIMIT", "1000");
+        sh.set_var("GIT_HTTP_LOW_SPEED_TIME", secs.to_string());
+    }
+    ensure_gh_authenticated(&sh, args.gh_hostname.as_deref())?;
+
+    let current_branch = verbose_read(cmd!(sh, "git branch --show-current").quiet())
         .context("getting current branch")?;
 
-    let repo_data = get_repo_data(&sh).context("getting repo data")?;
+    let upstream_url = match &args.base_remote {
+        Some(remote) => Some(
+            verbose_read(cmd!(sh, "git remote get-url {remote}").quiet())
+                .with_context(|| format!("getting the url of {remote}"))?,
+        ),
+        None => None,
+    };
+    // the open PRs are only needed to find the one to merge from the current branch
+    let open_prs_from = (args.branch_or_pr_number.is_empty()
+        && args.github_ref.is_none()
+        && args.batch_file.is_none()
+        && !current_branch.is_empty())
+    .then_some(current_branch.as_str());
+    let mut repo_data =
+        get_repo_data(&sh, upstream_url.as_deref(), open_prs_from).context("getting repo data")?;
+    if upstream_url.is_some() {
+        // otherwise gh may well pick the fork when looking up PRs
+        let RepoData {
+            owner_login, name, ..
+        } = &repo_data;
+        sh.set_var("GH_REPO", format!("{owner_login}/{name}"));
+    }
+    if args.warn_no_required_checks {
+        match required_checks(&sh, &repo_data, &repo_data.default_branch) {
+            Ok(required) => repo_data.has_required_ci = Some(!required.is_empty()),
+            Err(err) => eprintln!("warning: {err:#}"),
+        }
+    }
+
+    if let Some(github_ref) = &args.github_ref {
+        let number = pr_number_for_ref(&sh, github_ref)?;
+        args.branch_or_pr_number = vec![number.to_string()];
+    }
+
+    // the settings for each PR in the batch are worked out up front, so that a mistake on a
+    // later line doesn't leave the batch half merged
+    let mut batch = Vec::new();
+    if let Some(path) = &args.batch_file {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let entries =
+            batch::parse(&contents).with_context(|| format!("parsing {}", path.display()))?;
+        if entries.is_empty() {
+            bail!("{} does not list any PRs", path.display());
+        }
+        for batch::Entry { target, flags } in entries {
+            if args.atomic && !flags.is_empty() {
+                bail!("{target}: per-PR flags cannot be used with --atomic");
+            }
+            let mut entry_args = args.clone();
+            entry_args
+                .try_update_from(
+                    std::iter::once("merge-pr").chain(flags.iter().map(String::as_str)),
+                )
+                .map_err(|err| anyhow!("{target} in {}: {err}", path.display()))?;
+            batch.push((target, entry_args));
+        }
+    }
+
+    let targets = if !batch.is_empty() {
+        batch
+            .iter()
+            .map(|(target, _)| Some(target.as_str()))
+            .collect()
+    } else if args.branch_or_pr_number.is_empty() {
+        vec![None]
+    } else {
+        args.branch_or_pr_number
+            .iter()
+            .map(|target| Some(target.as_str()))
+            .collect()
+    };
 
-    let pr_data = match (args.branch_or_pr_number, current_branch.as_str()) {
-        (None, branch) if branch == repo_data.default_branch => {
-            bail!("on default branch; must specify the PR number or branch name to merge")
+    let hooks_dir = match &args.hooks_dir {
+        Some(dir) => dir.clone(),
+        // `--git-path` respects `core.hooksPath`
+        None => {
+            let hooks = verbose_read(cmd!(sh, "git rev-parse --git-path hooks").quiet())
+                .context("finding the hooks directory")?;
+            sh.current_dir().join(hooks).join("merge-pr")
         }
-        (None, _) => PrData::from_branch(&sh, &current_branch)?,
-        (Some(branch), _) => PrData::parse(&sh, &branch, &repo_data)?,
     };
 
-    let branch = &pr_data.branch;
-    let qualified_branch = pr_data.qualified_branch();
-    let qualified_branch = qualified_branch.as_ref();
-    let head_remote = pr_data
-        .remote
+    let progress_file = args.progress_file.as_ref().map(|path| {
+        path.clone().unwrap_or_else(|| {
+            std::env::temp_dir().join(format!("merge-pr-{}.json", std::process::id()))
+        })
+    });
+
+    if let Some(path) = &progress_file {
+        left_behind().files.push(path.clone());
+    }
+
+    let session = Session {
+        sh: &sh,
+        args: &args,
+        repo_data: &repo_data,
+        current_branch: &current_branch,
+        hooks_dir: &hooks_dir,
+        progress_file: progress_file.as_deref(),
+    };
+    let states = if args.atomic {
+        session.merge_atomically(&targets)?
+    } else if !batch.is_empty() {
+        let jobs = batch
+            .iter()
+            .map(|(target, entry_args)| (Some(target.as_str()), entry_args))
+            .collect::<Vec<_>>();
+        session.merge_each(&jobs)?
+    } else {
+        let jobs = targets
+            .iter()
+            .map(|&target| (target, &args))
+            .collect::<Vec<_>>();
+        session.merge_each(&jobs)?
+    };
+    if let Some(path) = args
+        .save_state_on_success
         .as_ref()
-        .map(|remote| remote.name.as_str())
-        .unwrap_or(&args.remote);
+        .filter(|_| !args.dry_run_commands)
+    {
+        let mut json = String::new();
+        for state in &states {
+            json += &serde_json::to_string(state).context("serializing merge state")?;
+            json.push('\n');
+        }
+        std::fs::write(path, json).with_context(|| format!("writing {}", path.display()))?;
+    }
+    if let Some((path, state)) = args
+        .github_output
+        .as_ref()
+        .filter(|_| !args.dry_run_commands)
+        .zip(states.last())
+    {
+        write_github_output(path, state)
+            .with_context(|| format!("writing github output to {}", path.display()))?;
+    }
+    if let Some(path) = &progress_file {
+        // it's never written unless waiting on CI
+        let _ = std::fs::remove_file(path);
+    }
+    if args.dry_run_commands {
+        eprintln!("[DRY-RUN] none of the commands above were run");
+    }
+    Ok(())
+}
 
-    // get review and current ci status
-    let mut status = poll_status(&sh, qualified_branch)?;
-    if !status.is_approved() {
-        bail!("{branch} has not been approved");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ci_state_round_trips() {
+        for state in [CiState::Success, CiState::Incomplete, CiState::Fail] {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(serde_json::from_str::<CiState>(&json).unwrap(), state);
+        }
+        assert_eq!(serde_json::to_string(&CiState::Fail).unwrap(), r#""fail""#);
+    }
+
+    #[test]
+    fn ci_state_keeps_unknown_values() {
+        let state = serde_json::from_str::<CiState>(r#""cancelled""#).unwrap();
+        assert_eq!(state, CiState::Unknown("cancelled".to_owned()));
+        assert_eq!(serde_json::to_string(&state).unwrap(), r#""cancelled""#);
+        assert!(serde_json::from_str::<CiState>("3").is_err());
     }
 
-    if args.wait_for_ci {
-        // retry until success or fail
-        let mut sp = Spinner::new(Spinners::Dots, "waiting for CI...".into());
-        while status.ci_state() == CiState::Incomplete {
-            std::thread::sleep(Duration::from_secs_f64(args.ci_poll_interval));
-            status = poll_status(&sh, qualified_branch)?;
+    #[test]
+    fn no_wait_is_zero() {
+        let mut wait = WaitStrategy::NoWait;
+        assert_eq!(wait.next_interval(), Duration::ZERO);
+        assert_eq!(wait.next_interval(), Duration::ZERO);
+        assert!(matches!(
+            WaitStrategy::fixed(HumanDuration(0.0)),
+            WaitStrategy::NoWait
+        ));
+    }
+
+    #[test]
+    fn fixed_interval_stays_constant() {
+        let mut wait = WaitStrategy::fixed(HumanDuration(2.5));
+        for _ in 0..3 {
+            assert_eq!(wait.next_interval(), Duration::from_millis(2500));
         }
-        sp.stop_with_newline();
     }
 
-    if !args.ignore_ci && status.ci_state() != CiState::Success {
-        for non_success in status
-            .check_runs()
-            .filter(|check_run| !check_run.is_successy())
-        {
-            let state = non_success.state();
-            let CheckRun {
-                name,
-                workflow_name,
-                ..
-            } = non_success;
-            println!("{workflow_name} / {name}: {state:?}");
+    #[test]
+    fn exponential_backoff_grows_up_to_max() {
+        let mut wait = WaitStrategy::ExponentialBackoff {
+            interval: Duration::from_secs(1),
+            factor: 2.0,
+            max: Duration::from_secs(5),
+            jitter: 0.0,
+        };
+        let intervals = (0..5)
+            .map(|_| wait.next_interval().as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(intervals, [1, 2, 4, 5, 5]);
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        for _ in 0..100 {
+            let factor = jitter(0.25);
+            assert!((1.0..1.25).contains(&factor), "{factor}");
+        }
+        let mut wait = WaitStrategy::ExponentialBackoff {
+            interval: Duration::from_secs(4),
+            factor: 1.0,
+            max: Duration::from_secs(10),
+            jitter: 0.5,
+        };
+        for _ in 0..100 {
+            let interval = wait.next_interval();
+            assert!(
+                (Duration::from_secs(4)..Duration::from_secs(6)).contains(&interval),
+                "{interval:?}"
+            );
         }
-        bail!("some ci checks are incomplete or unsuccessful");
     }
 
-    if args.dry_run {
-        println!("all checks OK but aborting due to dry run");
-        return Ok(());
+    fn secs(duration: &str) -> Result<f64, String> {
+        duration.parse::<HumanDuration>().map(HumanDuration::secs)
     }
 
-    let remote = args.remote.as_str();
+    #[test]
+    fn human_duration_parses_plain_seconds() {
+        assert_eq!(secs("5"), Ok(5.0));
+        assert_eq!(secs("2.5"), Ok(2.5));
+        assert_eq!(secs(" 0 "), Ok(0.0));
+    }
 
-    // ensure that the branch is at the tip of its base for a linear history
-    let base = status.base_ref_name;
-    cmd!(sh, "git fetch --no-all --no-tags {head_remote} {branch}")
-        .run()
-        .context("git fetch")?;
-    // try checking out a local branch
-    if cmd!(sh, "git checkout --no-guess {branch}").run().is_err() {
-        // try checking out a remote branch
-        cmd!(
-            sh,
-            "git checkout --no-guess -b {branch} --track {head_remote}/{branch} --"
-        )
-        .run()
-        .context("git checkout branch")?;
+    #[test]
+    fn human_duration_parses_units() {
+        assert_eq!(secs("30s"), Ok(30.0));
+        assert_eq!(secs("2m30s"), Ok(150.0));
+        assert_eq!(secs("1h"), Ok(3600.0));
+        assert_eq!(secs("1.5m"), Ok(90.0));
+        assert_eq!(secs("250ms"), Ok(0.25));
     }
 
-    // Before we rebase, make sure that the state on the local branch corresponds to the one on
-    // remote. Local branch state could differ if there was already a branch that wasn't in sync
-    // with the remote. In this case we don't want to do a rebase and `push -f` as that would
-    // overwrite the remote branch and merge local state, instead of remote.
-    if !local_branch_matches_remote(&sh, head_remote, branch)? {
-        bail!("local branch {branch} differs from remote branch {head_remote}/{branch}");
+    #[test]
+    fn human_duration_rejects_invalid_input() {
+        for invalid in ["", "abc", "5x", "2m30", "-1", "m", "1.2.3s", "inf"] {
+            assert!(secs(invalid).is_err(), "{invalid}");
+        }
     }
 
-    cmd!(sh, "git fetch {remote}")
-        .run()
-        .context(format!("fetching {remote}"))?;
+    #[test]
+    fn human_duration_display_round_trips() {
+        for (secs, display) in [
+            (0.0, "0s"),
+            (2.5, "2.5s"),
+            (30.0, "30s"),
+            (90.0, "1m30s"),
+            (150.0, "2m30s"),
+            (3600.0, "1h"),
+            (3725.0, "1h2m5s"),
+        ] {
+            let duration = HumanDuration(secs);
+            assert_eq!(duration.to_string(), display);
+            assert_eq!(display.parse::<HumanDuration>().unwrap().secs(), secs);
+        }
+    }
 
-    let rebase_result = if args.no_autosquash {
-        cmd!(sh, "git rebase {remote}/{base}").run()
-    } else {
-        // the command is a little funky because autosquash is a noop on non-interactive rebase
-        // but of course, we want a non-interactive rebase here
-        // the solution is to pass a config which specifies a noop interactive editor
-        cmd!(
-            sh,
-            "git -c sequence.editor=: rebase -i --autosquash {remote}/{base}"
-        )
-        .run()
-    };
-    if rebase_result.is_err() {
-        cmd!(sh, "git rebase --abort")
-            .run()
-            .context("aborting rebase")?;
-        bail!("{branch} did not cleanly rebase onto {remote}/{base}; do so manually and try again");
-    }
-
-    // if rebase moved the tip then force-push to ensure github is tracking the new history
-    // this resets CI, but doesn't mess with the approvals. We can assume CI is OK, at this point
-    if !local_branch_matches_remote(&sh, head_remote, branch)? {
-        cmd!(sh, "git push --force-with-lease {head_remote} {branch}")
-            .run()
-            .context("force-pushing branch")?;
+    #[test]
+    fn parse_version_finds_the_first_version() {
+        assert_eq!(parse_version("git version 2.39.0"), Some([2, 39, 0]));
+        assert_eq!(
+            parse_version("gh version 2.40.1 (2024-01-01)"),
+            Some([2, 40, 1])
+        );
+        assert_eq!(parse_version("2.39.5 (Apple Git-154)"), Some([2, 39, 5]));
+        assert_eq!(parse_version("2.39.0.windows.1"), Some([2, 39, 0]));
+        assert_eq!(parse_version("2.20"), Some([2, 20, 0]));
+        assert_eq!(parse_version("git version unknown"), None);
+    }
 
-        // Because we're pushing again to the remote base branch in a moment, let's wait, to let github
-        // handle this push first. This is desirable, because checks get canceled and appear as failed
-        // if we merge (and delete) the branch too quickly after updating it.
-        std::thread::sleep(std::time::Duration::from_secs_f64(args.wait_after_rebase));
-    }
-
-    // we can now actually merge this to main without breaking anything
-    cmd!(sh, "git checkout {base}")
-        .run()
-        .context("checking out base")?;
-    cmd!(sh, "git merge {branch} --ff-only")
-        .run()
-        .context("performing ff-only merge to base")?;
-
-    // in principle we can now just push; github has some magic to ensure that if you are pushing main
-    // to a commit which is at the tip of an approved pr, then it counts it as a manual merge operation
-    // and is permitted.
-    //
-    // sometimes it takes a few seconds for github to catch up, so in the event of a failure we try again
-    // a bit later.
-    let push_result = cmd!(sh, "git push {remote} {base}").run();
-    if push_result.is_err() {
-        println!("this is normal; retrying in {}s", args.push_retry_interval);
-        std::thread::sleep(std::time::Duration::from_secs_f64(args.push_retry_interval));
-        cmd!(sh, "git push {remote} {base}")
-            .run()
-            .context("2nd attempt to push to base")?;
-    }
-
-    if !args.retain_branch {
-        cmd!(sh, "git branch -D {branch}")
-            .run()
-            .context("removing merged branch")?;
+    #[test]
+    fn closing_references_finds_keywords() {
+        assert_eq!(closing_references("Fixes #1 and closes #2"), [1, 2]);
+        assert_eq!(closing_references("RESOLVES #3"), [3]);
+        assert_eq!(closing_references("fixes: #4"), [4]);
+        assert_eq!(closing_references("(closes #5)."), [5]);
+        assert_eq!(closing_references("fixes #7, fixed #7"), [7]);
     }
 
-    Ok(())
+    #[test]
+    fn closing_references_ignores_other_words() {
+        assert!(closing_references("prefixes #6").is_empty());
+        assert!(closing_references("see #8").is_empty());
+        assert!(closing_references("fixes 9").is_empty());
+        assert!(closing_references("fixes").is_empty());
+    }
+
+    #[test]
+    fn repo_config_overrides_profile_flags() {
+        let mut args = Args::try_parse_from(["merge-pr"]).unwrap();
+        let toplevel = Path::new("/");
+        let file = Config {
+            require_linear_history: Some(false),
+            ..Config::default()
+        };
+        let profile = Config {
+            require_linear_history: Some(true),
+            fetch_tags: Some(true),
+            ..Config::default()
+        };
+        let from_config = args.apply_config(file, toplevel, &[]);
+        let from_profile = args.apply_config(profile, toplevel, &from_config);
+        assert!(!args.require_linear_history);
+        assert!(args.fetch_tags);
+        assert_eq!(from_config, ["require_linear_history"]);
+        assert_eq!(from_profile, ["fetch_tags"]);
+    }
 }
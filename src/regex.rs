@@ -0,0 +1,354 @@
+//! Just enough of regular expressions to check text like a PR title against a pattern.
+//!
+//! Literals, `.`, character classes (`[a-z]`, `[^0-9]`), `\d`, `\w`, `\s` and their negations,
+//! groups (`(...)` or `(?:...)`), alternation, the anchors `^` and `$`, and the greedy
+//! quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}` are understood. As with most regex
+//! engines, a pattern matches anywhere in the text unless anchored. Matching backtracks, which
+//! is fine for the short patterns and texts this is used on.
+
+use std::{iter::Peekable, str::Chars};
+
+use anyhow::{anyhow, bail, Result};
+
+#[derive(Debug)]
+pub struct Regex {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Alt(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+#[derive(Debug)]
+enum ClassItem {
+    Range(char, char),
+    /// `\d`, `\w` or `\s`, by its letter, possibly negated.
+    Perl(char, bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            Self::Range(lo, hi) => (lo..=hi).contains(&c),
+            Self::Perl(kind, negated) => {
+                let matches = match kind {
+                    'd' => c.is_ascii_digit(),
+                    'w' => c.is_alphanumeric() || c == '_',
+                    _ => c.is_whitespace(),
+                };
+                matches != negated
+            }
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut chars = pattern.chars().peekable();
+        let mut branches = parse_alt(&mut chars)?;
+        if chars.next().is_some() {
+            bail!("unmatched `)`");
+        }
+        let nodes = if branches.len() == 1 {
+            branches.remove(0)
+        } else {
+            vec![Node::Alt(branches)]
+        };
+        Ok(Self { nodes })
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        (0..=text.len()).any(|start| match_nodes(&self.nodes, &text, start, &|_| true))
+    }
+}
+
+/// Parse alternatives separated by `|`, up to the end of the pattern or an unmatched `)`.
+fn parse_alt(chars: &mut Peekable<Chars<'_>>) -> Result<Vec<Vec<Node>>> {
+    let mut branches = vec![parse_seq(chars)?];
+    while chars.next_if_eq(&'|').is_some() {
+        branches.push(parse_seq(chars)?);
+    }
+    Ok(branches)
+}
+
+fn parse_seq(chars: &mut Peekable<Chars<'_>>) -> Result<Vec<Node>> {
+    let mut nodes = Vec::new();
+    while let Some(&c) = chars.peek() {
+        if c == '|' || c == ')' {
+            break;
+        }
+        chars.next();
+        let node = match c {
+            '(' => {
+                if chars.next_if_eq(&'?').is_some() && chars.next_if_eq(&':').is_none() {
+                    bail!("only `(?:` groups are supported");
+                }
+                let branches = parse_alt(chars)?;
+                if chars.next_if_eq(&')').is_none() {
+                    bail!("unmatched `(`");
+                }
+                Node::Alt(branches)
+            }
+            '[' => parse_class(chars)?,
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '\\' => match parse_escape(chars)? {
+                ClassItem::Range(c, _) => Node::Char(c),
+                perl => Node::Class {
+                    items: vec![perl],
+                    negated: false,
+                },
+            },
+            '*' | '+' | '?' | '{' => bail!("nothing to repeat before `{c}`"),
+            c => Node::Char(c),
+        };
+        nodes.push(parse_repeat(chars, node)?);
+    }
+    Ok(nodes)
+}
+
+/// Wrap `node` in any quantifier which follows it.
+fn parse_repeat(chars: &mut Peekable<Chars<'_>>, node: Node) -> Result<Node> {
+    let (min, max) = match chars.next_if(|c| matches!(c, '*' | '+' | '?' | '{')) {
+        Some('*') => (0, None),
+        Some('+') => (1, None),
+        Some('?') => (0, Some(1)),
+        Some(_) => {
+            let mut bounds = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => bounds.push(c),
+                    None => bail!("unterminated `{{`"),
+                }
+            }
+            let invalid = || anyhow!("invalid repetition `{{{bounds}}}`");
+            let bound = |bound: &str| bound.trim().parse::<u32>().map_err(|_| invalid());
+            let (min, max) = match bounds.split_once(',') {
+                None => (bound(&bounds)?, Some(bound(&bounds)?)),
+                Some((min, "")) => (bound(min)?, None),
+                Some((min, max)) => (bound(min)?, Some(bound(max)?)),
+            };
+            if max.is_some_and(|max| max < min) {
+                return Err(invalid());
+            }
+            (min, max)
+        }
+        None => return Ok(node),
+    };
+    Ok(Node::Repeat {
+        node: Box::new(node),
+        min,
+        max,
+    })
+}
+
+/// Parse a bracketed class, after its `[`.
+fn parse_class(chars: &mut Peekable<Chars<'_>>) -> Result<Node> {
+    let negated = chars.next_if_eq(&'^').is_some();
+    let mut items = Vec::new();
+    // a leading `]` is literal
+    if chars.next_if_eq(&']').is_some() {
+        items.push(ClassItem::Range(']', ']'));
+    }
+    loop {
+        let lo = match chars.next() {
+            Some(']') => break,
+            Some('\\') => match parse_escape(chars)? {
+                ClassItem::Range(c, _) => c,
+                perl => {
+                    items.push(perl);
+                    continue;
+                }
+            },
+            Some(c) => c,
+            None => bail!("unterminated `[`"),
+        };
+        // a `-` just before the closing `]` is literal
+        let mut ahead = chars.clone();
+        if ahead.next() != Some('-') || matches!(ahead.next(), Some(']') | None) {
+            items.push(ClassItem::Range(lo, lo));
+            continue;
+        }
+        chars.next();
+        let hi = match chars.next() {
+            Some('\\') => match parse_escape(chars)? {
+                ClassItem::Range(c, _) => c,
+                ClassItem::Perl(..) => bail!("invalid range in `[`"),
+            },
+            Some(c) => c,
+            None => bail!("unterminated `[`"),
+        };
+        if hi < lo {
+            bail!("invalid range `{lo}-{hi}`");
+        }
+        items.push(ClassItem::Range(lo, hi));
+    }
+    Ok(Node::Class { items, negated })
+}
+
+/// Parse what follows a `\`, as a single character or a Perl class.
+fn parse_escape(chars: &mut Peekable<Chars<'_>>) -> Result<ClassItem> {
+    let c = match chars.next() {
+        Some(c @ ('d' | 'w' | 's')) => return Ok(ClassItem::Perl(c, false)),
+        Some(c @ ('D' | 'W' | 'S')) => return Ok(ClassItem::Perl(c.to_ascii_lowercase(), true)),
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some(c) if !c.is_alphanumeric() => c,
+        Some(c) => bail!("unsupported escape `\\{c}`"),
+        None => bail!("dangling backslash"),
+    };
+    Ok(ClassItem::Range(c, c))
+}
+
+/// Whether `nodes` match `text` from `pos` such that `then` accepts where they stop.
+fn match_nodes(nodes: &[Node], text: &[char], pos: usize, then: &dyn Fn(usize) -> bool) -> bool {
+    let Some((node, rest)) = nodes.split_first() else {
+        return then(pos);
+    };
+    let single = |matches: bool| matches && match_nodes(rest, text, pos + 1, then);
+    match node {
+        Node::Char(c) => single(text.get(pos) == Some(c)),
+        Node::Any => single(pos < text.len()),
+        Node::Class { items, negated } => single(
+            text.get(pos)
+                .is_some_and(|&c| items.iter().any(|item| item.matches(c)) != *negated),
+        ),
+        Node::Start => pos == 0 && match_nodes(rest, text, pos, then),
+        Node::End => pos == text.len() && match_nodes(rest, text, pos, then),
+        Node::Alt(branches) => branches.iter().any(|branch| {
+            match_nodes(branch, text, pos, &|end| match_nodes(rest, text, end, then))
+        }),
+        Node::Repeat { node, min, max } => match_repeat(node, *min, *max, 0, text, pos, &|end| {
+            match_nodes(rest, text, end, then)
+        }),
+    }
+}
+
+/// Greedily match `node` between `min` and `max` times, `count` of which already have.
+fn match_repeat(
+    node: &Node,
+    min: u32,
+    max: Option<u32>,
+    count: u32,
+    text: &[char],
+    pos: usize,
+    then: &dyn Fn(usize) -> bool,
+) -> bool {
+    let again = max.is_none_or(|max| count < max)
+        && match_nodes(std::slice::from_ref(node), text, pos, &|end| {
+            // an empty match can't get any further, so stop once it has done enough
+            (end != pos || count < min) && match_repeat(node, min, max, count + 1, text, end, then)
+        });
+    again || (count >= min && then(pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    fn error(pattern: &str) -> String {
+        Regex::new(pattern).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn anchors_and_alternation() {
+        let conventional = r"^(feat|fix|chore)(\(.+\))?: ";
+        assert!(is_match(conventional, "feat: add a flag"));
+        assert!(is_match(conventional, "fix(config): trailing commas"));
+        assert!(!is_match(conventional, "feature: add a flag"));
+        assert!(!is_match(conventional, "a feat: add a flag"));
+        assert!(is_match("fix", "a fix for it"));
+        assert!(!is_match("^fix$", "a fix"));
+        assert!(is_match("^(?:a|b)c$", "bc"));
+        assert!(is_match("^a|b$", "ax"));
+        assert!(!is_match("^(a|b)$", "ax"));
+    }
+
+    #[test]
+    fn repetition_bounds() {
+        assert!(is_match("^a{2}$", "aa"));
+        assert!(!is_match("^a{2}$", "aaa"));
+        assert!(is_match("^a{2,}$", "aaaa"));
+        assert!(!is_match("^a{2,}$", "a"));
+        assert!(is_match("^a{1,3}$", "aaa"));
+        assert!(!is_match("^a{1,3}$", "aaaa"));
+        assert!(is_match("^ab?c+$", "acc"));
+        assert_eq!(error("a{3,1}"), "invalid repetition `{3,1}`");
+        assert_eq!(error("a{x}"), "invalid repetition `{x}`");
+        assert_eq!(error("a{2"), "unterminated `{`");
+    }
+
+    #[test]
+    fn classes() {
+        assert!(is_match("^[]a]+$", "]a]"));
+        assert!(is_match("^[a-]+$", "a-a"));
+        assert!(is_match("^[-a]$", "-"));
+        assert!(is_match(r"^[\]]$", "]"));
+        assert!(is_match("^[A-Z]+-[0-9]+$", "PROJ-12"));
+        assert!(!is_match("^[A-Z]+$", "Proj"));
+        assert!(is_match("^[^a-c]$", "d"));
+        assert!(!is_match("^[^a-c]$", "b"));
+        assert!(!is_match("^[^-]$", "-"));
+        assert_eq!(error("[z-a]"), "invalid range `z-a`");
+        assert_eq!(error("[ab"), "unterminated `[`");
+    }
+
+    #[test]
+    fn perl_classes() {
+        assert!(is_match(r"^\d+$", "123"));
+        assert!(!is_match(r"^\d+$", "12a"));
+        assert!(is_match(r"^\D+$", "abc"));
+        assert!(is_match(r"^\w+$", "snake_case1"));
+        assert!(!is_match(r"^\w+$", "kebab-case"));
+        assert!(is_match(r"^\W$", "-"));
+        assert!(is_match(r"^a\sb$", "a\tb"));
+        assert!(!is_match(r"^\S+$", "a b"));
+        assert!(is_match(r"^[\d\s]+$", "1 2"));
+        assert!(is_match(r"^[^\d]+$", "abc"));
+        assert!(is_match(r"^\.\*$", ".*"));
+    }
+
+    #[test]
+    fn empty_repeats_terminate() {
+        assert!(is_match("^(a*)*$", "aaa"));
+        assert!(!is_match("^(a*)*$", "aab"));
+        assert!(is_match("^(a*)*b$", "aaab"));
+        assert!(is_match("^(a|)+$", "aa"));
+        assert!(is_match("^(a?){3}$", "a"));
+        assert!(is_match("^()*$", ""));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error("(a"), "unmatched `(`");
+        assert_eq!(error("a)"), "unmatched `)`");
+        assert_eq!(error(r"a\"), "dangling backslash");
+        assert_eq!(error(r"\q"), "unsupported escape `\\q`");
+        assert_eq!(error("*a"), "nothing to repeat before `*`");
+        assert_eq!(error("a|+"), "nothing to repeat before `+`");
+        assert_eq!(error("a**"), "nothing to repeat before `*`");
+        assert_eq!(error("(?i)a"), "only `(?:` groups are supported");
+    }
+}